@@ -2,7 +2,7 @@
   (define i 10)
   (while (!= i 0) (begin
     (display "i^2 = " (* i i) "\n")
-    (define i (- i 1))
+    (set! i (- i 1))
     )
   )
 )
\ No newline at end of file
@@ -0,0 +1,10 @@
+(begin
+  (define i 0)
+  (define sum 0)
+  (while (< i 2000000) (begin
+    (set! sum (+ sum (* i i)))
+    (set! i (+ i 1))
+    )
+  )
+  (display sum "\n")
+)
@@ -0,0 +1,18 @@
+; Characters: literals, classification and conversion.
+(define letters (list #\S #\c #\h #\e #\m #\e #\space #\4 #\2))
+(write letters)
+(newline)
+
+(define (describe c)
+  (cond ((char-alphabetic? c) (list c 'letter (char-upcase c) (char-downcase c)))
+        ((char-numeric? c) (list c 'digit (- (char->integer c) (char->integer #\0))))
+        ((char-whitespace? c) (list 'whitespace))
+        (else (list c 'other))))
+
+(define (each xs)
+  (when (pair? xs)
+    (display (describe (car xs)) "\n")
+    (each (cdr xs))))
+(each letters)
+
+(display "char 955 is " (integer->char 955) ", a < b < c: " (char<? #\a #\b #\c) "\n")
@@ -0,0 +1,27 @@
+; Derived conditionals: cond, case, when, unless, and, or, not.
+(define (fizzbuzz n)
+  (cond ((= (% n 15) 0) "fizzbuzz")
+        ((= (% n 3) 0) "fizz")
+        ((= (% n 5) 0) "buzz")
+        (else n)))
+
+(define i 1)
+(while (<= i 15)
+  (display (fizzbuzz i) "\n")
+  (set! i (+ i 1)))
+
+(define (describe day)
+  (case day
+    ((saturday sunday) 'weekend)
+    ((monday tuesday wednesday thursday friday) 'weekday)
+    (else => (lambda (unknown) (list unknown 'is 'not 'a 'day)))))
+(display (describe 'sunday) " " (describe 'monday) " " (describe 'someday) "\n")
+
+(when (and (> 3 1) (< 1 3))
+  (display "both comparisons hold\n"))
+(unless (or (= 1 2) (not #t))
+  (display "neither alternative holds\n"))
+
+; `and` and `or` return the value that decided them.
+(display (and #t 42) " " (or #f 'fallback) "\n")
+(display (cond ((> 10 5) => (lambda (yes) (list 'test 'gave yes)))) "\n")
@@ -0,0 +1,43 @@
+; First-class continuations with call/cc.
+
+; Early exit: `return` jumps straight out of the loop.
+(define (first-negative numbers)
+  (call/cc
+   (lambda (return)
+     (let loop ((rest numbers))
+       (cond ((null? rest) #f)
+             ((< (car rest) 0) (return (car rest)))
+             (else (loop (cdr rest))))))))
+(display "first negative: " (first-negative '(3 1 -4 1 -5)) "\n")
+
+; A generator: each call resumes the walk where the previous one left off.
+(define (make-generator items)
+  (define return #f)
+  (define (walk items)
+    (unless (null? items)
+      (call/cc
+       (lambda (next)
+         (set! resume (lambda () (next #f)))
+         (return (car items))))
+      (walk (cdr items))))
+  (define (resume)
+    (walk items)
+    (return 'done))
+  (lambda ()
+    (call/cc
+     (lambda (caller)
+       (set! return caller)
+       (resume)))))
+(define next-color (make-generator '(red green blue)))
+(display "colors: " (next-color) " " (next-color) " " (next-color) " " (next-color) "\n")
+
+; dynamic-wind runs its after thunk even when a continuation escapes.
+(call/cc
+ (lambda (escape)
+   (dynamic-wind
+    (lambda () (display "opening the resource\n"))
+    (lambda ()
+      (display "working...\n")
+      (escape 'early)
+      (display "never printed\n"))
+    (lambda () (display "closing the resource\n")))))
@@ -0,0 +1,43 @@
+; Raising and catching exceptions with guard, error objects and handlers.
+
+(define (safe-div a b)
+  (guard (e ((error-object? e)
+             (display "caught: " (error-object-message e) "\n")
+             #f))
+    (if (= b 0)
+        (error "division by zero:" a)
+        (/ a b))))
+
+(display (safe-div 10 2) "\n")
+(display (safe-div 1 0) "\n")
+
+; Runtime errors are raised as error objects too.
+(display (guard (e (#t (error-object-message e)))
+           (car 5))
+         "\n")
+
+; Any object can be raised; clauses are tried like cond's.
+(define (classify thunk)
+  (guard (e ((symbol? e) (list 'symbol e))
+            ((string? e) (list 'string e))
+            ((and (pair? e) (eq? (car e) 'code) e) => cdr)
+            (else (list 'other e)))
+    (thunk)))
+
+(display (classify (lambda () (raise 'oops))) "\n")
+(display (classify (lambda () (raise "bad"))) "\n")
+(display (classify (lambda () (raise (cons 'code 42)))) "\n")
+(display (classify (lambda () (raise 7))) "\n")
+
+; A handler for raise-continuable can return a value in place of the raise.
+(display (with-exception-handler
+          (lambda (e) (* e 10))
+          (lambda () (+ 1 (raise-continuable 5))))
+         "\n")
+
+; The after thunks of dynamic-wind run when an exception escapes.
+(guard (e (#t (display "handled " e "\n")))
+  (dynamic-wind
+   (lambda () (display "enter\n"))
+   (lambda () (raise 'boom))
+   (lambda () (display "leave\n"))))
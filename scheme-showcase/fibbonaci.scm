@@ -9,10 +9,10 @@
   (while (!= n 0) (begin
     (display "fib(" i ") = " a "\n")
     (define next (+ a b))
-    (define a b)
-    (define b next)
-    (define n (- n 1))
-    (define i (+ i 1))
+    (set! a b)
+    (set! b next)
+    (set! n (- n 1))
+    (set! i (+ i 1))
     )
   )
 )
\ No newline at end of file
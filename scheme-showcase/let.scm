@@ -0,0 +1,22 @@
+; Local bindings with let, let*, letrec and named let.
+(define (hypotenuse-squared a b)
+  (let ((a2 (* a a))
+        (b2 (* b b)))
+    (+ a2 b2)))
+(display "3^2 + 4^2 = " (hypotenuse-squared 3 4) "\n")
+
+(let* ((width 6)
+       (height (* width 2))
+       (area (* width height)))
+  (display "a " width "x" height " rectangle has area " area "\n"))
+
+(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+         (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+  (display "is 42 even? " (even? 42) "\n"))
+
+; A named let is a loop: each call to `next` starts another iteration.
+(let next ((i 1) (a 1) (b 1))
+  (if (<= i 10)
+      (begin
+        (display "fib(" i ") = " a "\n")
+        (next (+ i 1) b (+ a b)))))
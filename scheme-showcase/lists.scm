@@ -0,0 +1,29 @@
+; Pairs and lists: building, walking and mutating cons cells.
+(define numbers (list 1 2 3 4 5))
+
+(define (sum xs)
+  (if (null? xs)
+      0
+      (+ (car xs) (sum (cdr xs)))))
+
+(define (squares xs)
+  (if (null? xs)
+      '()
+      (cons (* (car xs) (car xs)) (squares (cdr xs)))))
+
+(display "numbers: " numbers "\n")
+(display "sum: " (sum numbers) "\n")
+(display "squares: " (squares numbers) "\n")
+
+(define point (cons 3 4))
+(display "point: " point "\n")
+(set-cdr! point '(5 6))
+(display "extended: " point "\n")
+
+(write '(quoted "data" (nested . tail)))
+(newline)
+
+; Quasiquote builds data from a template, filling in the unquoted parts.
+(define evens '(2 4 6))
+(write `(sum ,(sum evens) of ,@evens and #(vector ,(car evens))))
+(newline)
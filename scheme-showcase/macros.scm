@@ -0,0 +1,57 @@
+; Hygienic macros with syntax-rules.
+(define-syntax swap!
+  (syntax-rules ()
+    ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))
+
+; The macro's own `tmp` cannot capture the user's `tmp`.
+(define tmp "first")
+(define other "second")
+(swap! tmp other)
+(display "after swap!: " tmp " " other "\n")
+
+; Literals and recursive expansion.
+(define-syntax my-cond
+  (syntax-rules (else)
+    ((_ (else body ...)) (begin body ...))
+    ((_ (test body ...) clause ...) (if test (begin body ...) (my-cond clause ...)))))
+(define (sign n)
+  (my-cond ((< n 0) "negative") ((= n 0) "zero") (else "positive")))
+(display "sign of -5: " (sign -5) "\n")
+
+; Nested ellipses.
+(define-syntax flatten
+  (syntax-rules ()
+    ((_ (x ...) ...) '(x ... ...))))
+(display "flattened: " (flatten (1 2) (3) (4 5 6)) "\n")
+
+; A loop built from a named let.
+(define-syntax repeat
+  (syntax-rules ()
+    ((_ n body ...) (let loop ((i 0)) (when (< i n) body ... (loop (+ i 1)))))))
+(define i 100)
+(repeat 3 (display "i is still " i "\n"))
+
+; Local macros.
+(define (cube x)
+  (let-syntax ((square (syntax-rules () ((_ e) (* e e)))))
+    (* x (square x))))
+(display "3 cubed is " (cube 3) "\n")
+
+; Procedural macros: the transformer is an ordinary procedure that receives
+; the operands as data and returns the code to run instead.
+(define-macro (my-unless test . body)
+  `(if ,test #f (begin ,@body)))
+(my-unless (> 1 2) (display "1 is not greater than 2\n"))
+
+; Without hygiene, gensym provides names that cannot capture the user's.
+(define-macro (rotate! a b c)
+  (let ((first (gensym)))
+    `(let ((,first ,a))
+       (set! ,a ,b)
+       (set! ,b ,c)
+       (set! ,c ,first))))
+(define x 1)
+(define y 2)
+(define z 3)
+(rotate! x y z)
+(display "rotated: " x " " y " " z "\n")
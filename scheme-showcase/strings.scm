@@ -0,0 +1,23 @@
+; The string library: everything indexes by character, not by byte.
+(define greeting "héllo, wörld")
+(display "length: " (string-length greeting) "\n")
+(display "shouting: " (string-upcase greeting) "\n")
+(display "first word: " (substring greeting 0 (string-index greeting #\,)) "\n")
+(display "where is wörld? " (string-contains greeting "wörld") "\n")
+
+(define csv "name,age,city")
+(define fields (string-split csv #\,))
+(write fields)
+(newline)
+(display (string-join fields " | ") "\n")
+
+(define (reverse-string s)
+  (let loop ((chars (string->list s)) (reversed '()))
+    (if (null? chars)
+        (list->string reversed)
+        (loop (cdr chars) (cons (car chars) reversed)))))
+(display (reverse-string "stressed") "\n")
+
+(display (string<? "apple" "banana" "cherry") " "
+         (string=? (symbol->string 'abc) "abc") " "
+         (string->symbol "made-from-a-string") "\n")
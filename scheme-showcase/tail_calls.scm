@@ -0,0 +1,21 @@
+; Calls in tail position reuse the caller's frame, so recursive loops run in
+; constant space no matter how many times they iterate.
+(define (count-up i n)
+  (if (< i n)
+      (count-up (+ i 1) n)
+      i))
+(display "counted to " (count-up 0 1000000) "\n")
+
+(define (sum-to n acc)
+  (if (= n 0)
+      acc
+      (begin
+        (define next (- n 1))
+        (sum-to next (+ acc n)))))
+(display "sum of 1..1000000 = " (sum-to 1000000 0) "\n")
+
+(define (even? n) (if (= n 0) #t (odd? (- n 1))))
+(define (odd? n) (if (= n 0) #f (even? (- n 1))))
+(display "1000001 is odd: " (odd? 1000001) "\n")
+
+(display "via apply: " (apply count-up '(0 1000000)) "\n")
@@ -0,0 +1,18 @@
+; Vectors: literals, indexing and the higher-order vector procedures.
+(define primes #(2 3 5 7 11))
+(display "primes: " primes "\n")
+(display "third prime: " (vector-ref primes 2) "\n")
+
+(define squares (make-vector 5 0))
+(define i 0)
+(while (< i 5)
+  (vector-set! squares i (* i i))
+  (set! i (+ i 1)))
+(display "squares: " squares "\n")
+
+(display "sums: " (vector-map + primes squares) "\n")
+(display "as a list: " (vector->list primes 1 4) "\n")
+
+(vector-for-each
+  (lambda (p) (display p " squared is " (* p p) "\n"))
+  #(1 2 3))
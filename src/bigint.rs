@@ -0,0 +1,373 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+const BASE: u64 = 1 << 32;
+
+/// Sign-magnitude arbitrary-precision integer with little-endian base 2^32
+/// limbs. Zero is always represented by an empty magnitude and no sign.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+fn trim(magnitude: &mut Vec<u32>) {
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+}
+
+fn compare_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    if a.len() != b.len() {
+        return a.len().cmp(&b.len());
+    }
+    for (x, y) in a.iter().rev().zip(b.iter().rev()) {
+        if x != y {
+            return x.cmp(y);
+        }
+    }
+    Ordering::Equal
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, limb) in long.iter().enumerate() {
+        let sum = *limb as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+/// Requires `a >= b`.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, limb) in a.iter().enumerate() {
+        let mut difference = *limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if difference < 0 {
+            difference += BASE as i64;
+            borrow = 1;
+        }
+        result.push(difference as u32);
+    }
+    trim(&mut result);
+    result
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let product = *x as u64 * *y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    trim(&mut result);
+    result
+}
+
+fn divrem_small(a: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+    for i in (0..a.len()).rev() {
+        let current = (remainder << 32) | a[i] as u64;
+        quotient[i] = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    trim(&mut quotient);
+    (quotient, remainder as u32)
+}
+
+fn shl_magnitude(a: &[u32], bits: u32) -> Vec<u32> {
+    let limbs = (bits / 32) as usize;
+    let bits = bits % 32;
+    let mut result = vec![0u32; limbs];
+    if bits == 0 {
+        result.extend_from_slice(a);
+    } else {
+        let mut carry = 0u32;
+        for limb in a {
+            result.push((limb << bits) | carry);
+            carry = limb >> (32 - bits);
+        }
+        result.push(carry);
+    }
+    trim(&mut result);
+    result
+}
+
+fn shr_magnitude(a: &[u32], bits: u32) -> Vec<u32> {
+    if bits == 0 {
+        return a.to_vec();
+    }
+    let mut result = vec![0u32; a.len()];
+    for i in 0..a.len() {
+        let high = if i + 1 < a.len() { a[i + 1] << (32 - bits) } else { 0 };
+        result[i] = (a[i] >> bits) | high;
+    }
+    trim(&mut result);
+    result
+}
+
+/// Knuth's algorithm D on magnitudes, returning `(quotient, remainder)`.
+fn divrem_magnitude(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if compare_magnitude(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if b.len() == 1 {
+        let (quotient, remainder) = divrem_small(a, b[0]);
+        let mut remainder = vec![remainder];
+        trim(&mut remainder);
+        return (quotient, remainder);
+    }
+
+    let shift = b[b.len() - 1].leading_zeros();
+    let v = shl_magnitude(b, shift);
+    let mut u = shl_magnitude(a, shift);
+    u.resize(a.len() + 1, 0);
+
+    let n = v.len();
+    let m = u.len() - n;
+    let mut quotient = vec![0u32; m];
+
+    for j in (0..m).rev() {
+        let numerator = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+        let mut qhat = numerator / v[n - 1] as u64;
+        let mut rhat = numerator % v[n - 1] as u64;
+        while qhat >= BASE || qhat * v[n - 2] as u64 > ((rhat << 32) | u[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += v[n - 1] as u64;
+            if rhat >= BASE {
+                break;
+            }
+        }
+
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = qhat * v[i] as u64 + carry;
+            carry = product >> 32;
+            let difference = u[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+            u[i + j] = difference as u32;
+            borrow = if difference < 0 { 1 } else { 0 };
+        }
+        let difference = u[j + n] as i64 - borrow - carry as i64;
+        u[j + n] = difference as u32;
+
+        if difference < 0 {
+            qhat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                u[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = qhat as u32;
+    }
+
+    trim(&mut quotient);
+    u.truncate(n);
+    let remainder = shr_magnitude(&u, shift);
+    (quotient, remainder)
+}
+
+impl BigInt {
+    fn new(negative: bool, mut magnitude: Vec<u32>) -> Self {
+        trim(&mut magnitude);
+        let negative = negative && !magnitude.is_empty();
+        Self {
+            negative,
+            magnitude,
+        }
+    }
+
+    pub fn from_i64(n: i64) -> Self {
+        let abs = n.unsigned_abs();
+        Self::new(n < 0, vec![abs as u32, (abs >> 32) as u32])
+    }
+
+    pub fn from_u64(n: u64) -> Self {
+        Self::new(false, vec![n as u32, (n >> 32) as u32])
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let mut abs = 0u64;
+        for (i, limb) in self.magnitude.iter().enumerate() {
+            abs |= (*limb as u64) << (32 * i);
+        }
+        if self.negative {
+            if abs <= i64::MAX as u64 + 1 {
+                Some((abs as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else {
+            i64::try_from(abs).ok()
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.as_bytes().first() {
+            Some(b'-') => (true, &text[1..]),
+            Some(b'+') => (false, &text[1..]),
+            _ => (false, text),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let mut magnitude: Vec<u32> = Vec::new();
+        for chunk in digits.as_bytes().chunks(9) {
+            let chunk = std::str::from_utf8(chunk).ok()?;
+            let scale = 10u32.pow(chunk.len() as u32);
+            magnitude = mul_magnitude(&magnitude, &[scale]);
+            magnitude = add_magnitude(&magnitude, &[chunk.parse().ok()?]);
+            trim(&mut magnitude);
+        }
+        Some(Self::new(negative, magnitude))
+    }
+
+    /// The number of bits in the magnitude; zero for zero.
+    pub fn bit_length(&self) -> u32 {
+        match self.magnitude.last() {
+            Some(top) => 32 * self.magnitude.len() as u32 - top.leading_zeros(),
+            None => 0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_one(&self) -> bool {
+        !self.negative && self.magnitude == [1]
+    }
+
+    pub fn neg(&self) -> Self {
+        Self::new(!self.negative, self.magnitude.clone())
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::new(false, self.magnitude.clone())
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        if self.negative == other.negative {
+            return Self::new(
+                self.negative,
+                add_magnitude(&self.magnitude, &other.magnitude),
+            );
+        }
+        match compare_magnitude(&self.magnitude, &other.magnitude) {
+            Ordering::Less => Self::new(
+                other.negative,
+                sub_magnitude(&other.magnitude, &self.magnitude),
+            ),
+            _ => Self::new(
+                self.negative,
+                sub_magnitude(&self.magnitude, &other.magnitude),
+            ),
+        }
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        Self::new(
+            self.negative != other.negative,
+            mul_magnitude(&self.magnitude, &other.magnitude),
+        )
+    }
+
+    /// Truncating division; the remainder takes the sign of the dividend.
+    /// Panics when `other` is zero.
+    pub fn div_rem(&self, other: &Self) -> (Self, Self) {
+        assert!(!other.is_zero(), "BigInt division by zero");
+        let (quotient, remainder) = divrem_magnitude(&self.magnitude, &other.magnitude);
+        (
+            Self::new(self.negative != other.negative, quotient),
+            Self::new(self.negative, remainder),
+        )
+    }
+
+    pub fn shl(&self, bits: u32) -> Self {
+        Self::new(self.negative, shl_magnitude(&self.magnitude, bits))
+    }
+
+    pub fn gcd(&self, other: &Self) -> Self {
+        let mut a = self.abs();
+        let mut b = other.abs();
+        while !b.is_zero() {
+            let (_, remainder) = a.div_rem(&b);
+            a = b;
+            b = remainder;
+        }
+        a
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => compare_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        let mut chunks = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            let (quotient, remainder) = divrem_small(&magnitude, 1_000_000_000);
+            chunks.push(remainder);
+            magnitude = quotient;
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
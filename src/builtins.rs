@@ -0,0 +1,804 @@
+use std::cmp::Ordering;
+use std::io;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use crate::environment::Globals;
+use crate::error::{Error, Result};
+use crate::number;
+use crate::value::{ErrorObject, Pair, Primitive, PrimitiveFn, Procedure, Value, Written};
+
+use std::cell::RefCell;
+
+use std::rc::Rc;
+
+/// The procedures written in Rust: (name, minimum and maximum argument
+/// counts, function).
+const PRIMITIVES: &[(&str, usize, Option<usize>, PrimitiveFn)] = &[
+    ("display", 0, None, display),
+    ("write", 1, Some(1), write),
+    ("newline", 0, Some(0), newline),
+    ("read", 0, Some(0), read),
+    ("read-line", 0, Some(0), read_line),
+    ("+", 0, None, operator_plus),
+    ("-", 1, None, operator_minus),
+    ("*", 0, None, operator_asterisk),
+    ("/", 1, None, operator_slash),
+    ("%", 2, Some(2), operator_percent),
+    ("<", 2, Some(2), operator_lt),
+    ("<=", 2, Some(2), operator_le),
+    (">", 2, Some(2), operator_gt),
+    (">=", 2, Some(2), operator_ge),
+    ("=", 2, Some(2), operator_eq),
+    ("!=", 2, Some(2), operator_ne),
+    ("exact->inexact", 1, Some(1), exact_to_inexact),
+    ("inexact->exact", 1, Some(1), inexact_to_exact),
+    ("numerator", 1, Some(1), numerator),
+    ("denominator", 1, Some(1), denominator),
+    ("eq?", 2, Some(2), is_eqv),
+    ("eqv?", 2, Some(2), is_eqv),
+    ("not", 1, Some(1), not),
+    ("boolean?", 1, Some(1), is_boolean),
+    ("boolean=?", 2, None, boolean_eq),
+    ("char?", 1, Some(1), is_char),
+    ("char->integer", 1, Some(1), char_to_integer),
+    ("integer->char", 1, Some(1), integer_to_char),
+    ("char-upcase", 1, Some(1), char_upcase),
+    ("char-downcase", 1, Some(1), char_downcase),
+    ("char-alphabetic?", 1, Some(1), is_char_alphabetic),
+    ("char-numeric?", 1, Some(1), is_char_numeric),
+    ("char-whitespace?", 1, Some(1), is_char_whitespace),
+    ("char=?", 2, None, char_eq),
+    ("char<?", 2, None, char_lt),
+    ("char>?", 2, None, char_gt),
+    ("char<=?", 2, None, char_le),
+    ("char>=?", 2, None, char_ge),
+    ("string?", 1, Some(1), is_string),
+    ("string-length", 1, Some(1), string_length),
+    ("string-ref", 2, Some(2), string_ref),
+    ("substring", 2, Some(3), substring),
+    ("string-append", 0, None, string_append),
+    ("string-copy", 1, Some(3), string_copy),
+    ("string-upcase", 1, Some(1), string_upcase),
+    ("string-downcase", 1, Some(1), string_downcase),
+    ("string-contains", 2, Some(2), string_contains),
+    ("string-split", 2, Some(2), string_split),
+    ("string-join", 1, Some(2), string_join),
+    ("string->list", 1, Some(3), string_to_list),
+    ("list->string", 1, Some(1), list_to_string),
+    ("string->symbol", 1, Some(1), string_to_symbol),
+    ("symbol->string", 1, Some(1), symbol_to_string),
+    ("symbol?", 1, Some(1), is_symbol),
+    ("gensym", 0, Some(1), gensym),
+    ("string=?", 2, None, string_eq),
+    ("string<?", 2, None, string_lt),
+    ("string>?", 2, None, string_gt),
+    ("string<=?", 2, None, string_le),
+    ("string>=?", 2, None, string_ge),
+    ("cons", 2, Some(2), cons),
+    ("car", 1, Some(1), car),
+    ("cdr", 1, Some(1), cdr),
+    ("list", 0, None, list),
+    ("append", 0, None, append),
+    ("null?", 1, Some(1), is_null),
+    ("pair?", 1, Some(1), is_pair),
+    ("set-car!", 2, Some(2), set_car),
+    ("set-cdr!", 2, Some(2), set_cdr),
+    ("make-vector", 1, Some(2), make_vector),
+    ("vector", 0, None, vector),
+    ("vector?", 1, Some(1), is_vector),
+    ("vector-length", 1, Some(1), vector_length),
+    ("vector-ref", 2, Some(2), vector_ref),
+    ("vector-set!", 3, Some(3), vector_set),
+    ("vector->list", 1, Some(3), vector_to_list),
+    ("list->vector", 1, Some(1), list_to_vector),
+    ("vector-fill!", 2, Some(4), vector_fill),
+    ("error", 1, None, error),
+    ("raise", 1, Some(1), raise),
+    ("error-object?", 1, Some(1), is_error_object),
+    ("error-object-message", 1, Some(1), error_object_message),
+    ("error-object-irritants", 1, Some(1), error_object_irritants),
+];
+
+fn procedure(&(name, min_args, max_args, func): &(&'static str, usize, Option<usize>, PrimitiveFn)) -> Value {
+    Value::Procedure(Rc::new(Procedure::Primitive(Primitive {
+        name,
+        min_args,
+        max_args,
+        func,
+    })))
+}
+
+/// The primitive procedure `name`, whatever the global of that name now holds.
+pub fn primitive(name: &str) -> Option<Value> {
+    PRIMITIVES.iter().find(|primitive| primitive.0 == name).map(procedure)
+}
+
+pub fn register(globals: &mut Globals) {
+    for primitive in PRIMITIVES {
+        globals.define(primitive.0, procedure(primitive));
+    }
+    globals.define("apply", Value::Procedure(Rc::new(Procedure::Apply)));
+    globals.define(
+        "call-with-current-continuation",
+        Value::Procedure(Rc::new(Procedure::CallCC)),
+    );
+}
+
+fn strip(mut s: String) -> String {
+    if s.ends_with('\n') {
+        s.pop();
+        if s.ends_with('\r') {
+            s.pop();
+        }
+    }
+    s
+}
+
+fn read_line(_args: &[Value]) -> Result<Value> {
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+
+    Ok(Value::string(&strip(input)))
+}
+
+fn read(_args: &[Value]) -> Result<Value> {
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+
+    let input = strip(input);
+    match number::parse(input.trim()) {
+        Some(value) => Ok(value),
+        None => Err(Error::type_error("read", "a number", &format!("{:?}", input))),
+    }
+}
+
+fn display(args: &[Value]) -> Result<Value> {
+    let mut stdout = io::stdout().lock();
+    for value in args {
+        write!(stdout, "{}", value)?;
+    }
+    stdout.flush()?;
+    Ok(Value::Unspecified)
+}
+
+fn write(args: &[Value]) -> Result<Value> {
+    print!("{}", Written(&args[0]));
+    io::stdout().flush()?;
+    Ok(Value::Unspecified)
+}
+
+fn newline(_args: &[Value]) -> Result<Value> {
+    println!();
+    Ok(Value::Unspecified)
+}
+
+fn operator_plus(args: &[Value]) -> Result<Value> {
+    match args {
+        [] => Ok(Value::Int(0)),
+        [a, b] => number::add(a, b),
+        _ => args
+            .iter()
+            .try_fold(Value::Int(0), |sum, value| number::add(&sum, value)),
+    }
+}
+
+fn operator_minus(args: &[Value]) -> Result<Value> {
+    if args.len() == 1 {
+        return number::negate(&args[0]);
+    }
+    args[1..]
+        .iter()
+        .try_fold(args[0].clone(), |difference, value| {
+            number::sub(&difference, value)
+        })
+}
+
+fn operator_asterisk(args: &[Value]) -> Result<Value> {
+    match args {
+        [a, b] => number::mul(a, b),
+        _ => args
+            .iter()
+            .try_fold(Value::Int(1), |product, value| number::mul(&product, value)),
+    }
+}
+
+fn operator_slash(args: &[Value]) -> Result<Value> {
+    if args.len() == 1 {
+        return number::div(&Value::Int(1), &args[0]);
+    }
+    args[1..]
+        .iter()
+        .try_fold(args[0].clone(), |quotient, value| {
+            number::div(&quotient, value)
+        })
+}
+
+fn operator_percent(args: &[Value]) -> Result<Value> {
+    number::rem(&args[0], &args[1])
+}
+
+fn operator_lt(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(
+        number::compare("<", &args[0], &args[1])? == Some(Ordering::Less),
+    ))
+}
+
+fn operator_le(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(
+        number::compare("<=", &args[0], &args[1])?,
+        Some(Ordering::Less | Ordering::Equal)
+    )))
+}
+
+fn operator_gt(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(
+        number::compare(">", &args[0], &args[1])? == Some(Ordering::Greater),
+    ))
+}
+
+fn operator_ge(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(
+        number::compare(">=", &args[0], &args[1])?,
+        Some(Ordering::Greater | Ordering::Equal)
+    )))
+}
+
+fn operator_eq(args: &[Value]) -> Result<Value> {
+    let equal = match (&args[0], &args[1]) {
+        (Value::String(a), Value::String(b)) => a == b,
+        (a, b) if number::is_number(a) && number::is_number(b) => {
+            number::compare("=", a, b)? == Some(Ordering::Equal)
+        }
+        _ => false,
+    };
+    Ok(Value::Bool(equal))
+}
+
+fn operator_ne(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(!operator_eq(args)?.is_true()))
+}
+
+/// `eq?` and `eqv?` are the same here: both compare by value for numbers,
+/// chars and symbols, and by identity for everything else.
+fn is_eqv(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(args[0].eqv(&args[1])))
+}
+
+fn not(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(!args[0].is_true()))
+}
+
+fn is_boolean(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(args[0], Value::Bool(_))))
+}
+
+fn boolean_eq(args: &[Value]) -> Result<Value> {
+    let mut booleans = args.iter().map(|value| match value {
+        Value::Bool(b) => Ok(*b),
+        value => Err(Error::type_error("boolean=?", "a boolean", value.type_name())),
+    });
+    let first = booleans.next().unwrap()?;
+    let mut equal = true;
+    for b in booleans {
+        equal &= b? == first;
+    }
+    Ok(Value::Bool(equal))
+}
+
+fn exact_to_inexact(args: &[Value]) -> Result<Value> {
+    number::exact_to_inexact(&args[0])
+}
+
+fn inexact_to_exact(args: &[Value]) -> Result<Value> {
+    number::inexact_to_exact(&args[0])
+}
+
+fn numerator(args: &[Value]) -> Result<Value> {
+    number::numerator(&args[0])
+}
+
+fn denominator(args: &[Value]) -> Result<Value> {
+    number::denominator(&args[0])
+}
+
+fn pair<'a>(procedure: &str, value: &'a Value) -> Result<&'a RefCell<Pair>> {
+    match value {
+        Value::Pair(pair) => Ok(pair),
+        value => Err(Error::type_error(procedure, "a pair", value.type_name())),
+    }
+}
+
+fn char_of(procedure: &str, value: &Value) -> Result<char> {
+    match value {
+        Value::Char(c) => Ok(*c),
+        value => Err(Error::type_error(procedure, "a char", value.type_name())),
+    }
+}
+
+fn is_char(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(args[0], Value::Char(_))))
+}
+
+fn char_to_integer(args: &[Value]) -> Result<Value> {
+    Ok(Value::Int(char_of("char->integer", &args[0])? as i64))
+}
+
+fn integer_to_char(args: &[Value]) -> Result<Value> {
+    let c = match &args[0] {
+        Value::Int(n) => u32::try_from(*n).ok().and_then(char::from_u32),
+        _ => None,
+    };
+    match c {
+        Some(c) => Ok(Value::Char(c)),
+        None => Err(Error::type_error(
+            "integer->char",
+            "a Unicode scalar value",
+            &args[0].to_string(),
+        )),
+    }
+}
+
+/// A case mapping that keeps `c` when it has no single-character equivalent.
+fn map_case<I: Iterator<Item = char>>(c: char, mapped: I) -> Value {
+    let mapped = mapped.collect::<Vec<char>>();
+    Value::Char(if mapped.len() == 1 { mapped[0] } else { c })
+}
+
+fn char_upcase(args: &[Value]) -> Result<Value> {
+    let c = char_of("char-upcase", &args[0])?;
+    Ok(map_case(c, c.to_uppercase()))
+}
+
+fn char_downcase(args: &[Value]) -> Result<Value> {
+    let c = char_of("char-downcase", &args[0])?;
+    Ok(map_case(c, c.to_lowercase()))
+}
+
+fn is_char_alphabetic(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(char_of("char-alphabetic?", &args[0])?.is_alphabetic()))
+}
+
+fn is_char_numeric(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(char_of("char-numeric?", &args[0])?.is_numeric()))
+}
+
+fn is_char_whitespace(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(char_of("char-whitespace?", &args[0])?.is_whitespace()))
+}
+
+/// Checks that `holds` is true of each adjacent pair of chars in `args`.
+fn compare_chars(procedure: &str, args: &[Value], holds: fn(char, char) -> bool) -> Result<Value> {
+    let chars = args
+        .iter()
+        .map(|value| char_of(procedure, value))
+        .collect::<Result<Vec<char>>>()?;
+    Ok(Value::Bool(chars.windows(2).all(|pair| holds(pair[0], pair[1]))))
+}
+
+fn char_eq(args: &[Value]) -> Result<Value> {
+    compare_chars("char=?", args, |a, b| a == b)
+}
+
+fn char_lt(args: &[Value]) -> Result<Value> {
+    compare_chars("char<?", args, |a, b| a < b)
+}
+
+fn char_gt(args: &[Value]) -> Result<Value> {
+    compare_chars("char>?", args, |a, b| a > b)
+}
+
+fn char_le(args: &[Value]) -> Result<Value> {
+    compare_chars("char<=?", args, |a, b| a <= b)
+}
+
+fn char_ge(args: &[Value]) -> Result<Value> {
+    compare_chars("char>=?", args, |a, b| a >= b)
+}
+
+fn cons(args: &[Value]) -> Result<Value> {
+    Ok(Value::cons(args[0].clone(), args[1].clone()))
+}
+
+fn car(args: &[Value]) -> Result<Value> {
+    Ok(pair("car", &args[0])?.borrow().car.clone())
+}
+
+fn cdr(args: &[Value]) -> Result<Value> {
+    Ok(pair("cdr", &args[0])?.borrow().cdr.clone())
+}
+
+fn list(args: &[Value]) -> Result<Value> {
+    Ok(Value::list(args.to_vec()))
+}
+
+/// Copies every list but the last, which becomes the shared tail.
+fn append(args: &[Value]) -> Result<Value> {
+    let Some((last, lists)) = args.split_last() else {
+        return Ok(Value::Nil);
+    };
+    let mut values = Vec::new();
+    for list in lists {
+        values.extend(
+            list.to_vec()
+                .ok_or_else(|| Error::type_error("append", "a list", list.type_name()))?,
+        );
+    }
+    Ok(Value::list_with_tail(values, last.clone()))
+}
+
+fn is_null(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(args[0], Value::Nil)))
+}
+
+fn is_pair(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(args[0], Value::Pair(_))))
+}
+
+fn set_car(args: &[Value]) -> Result<Value> {
+    pair("set-car!", &args[0])?.borrow_mut().car = args[1].clone();
+    Ok(Value::Unspecified)
+}
+
+fn set_cdr(args: &[Value]) -> Result<Value> {
+    pair("set-cdr!", &args[0])?.borrow_mut().cdr = args[1].clone();
+    Ok(Value::Unspecified)
+}
+
+/// `value` as an index below `limit` into a sequence of `length` elements.
+fn index(procedure: &str, value: &Value, limit: usize, length: usize) -> Result<usize> {
+    match value {
+        Value::Int(i) if *i >= 0 && (*i as usize) < limit => Ok(*i as usize),
+        Value::Int(_) | Value::BigInt(_) => Err(Error::OutOfRange {
+            procedure: procedure.to_string(),
+            index: value.to_string(),
+            length,
+        }),
+        value => Err(Error::type_error(procedure, "an exact integer", value.type_name())),
+    }
+}
+
+/// The optional `start` and `end` arguments of a sequence procedure.
+fn range(procedure: &str, args: &[Value], length: usize) -> Result<(usize, usize)> {
+    let start = match args.first() {
+        Some(start) => index(procedure, start, length + 1, length)?,
+        None => 0,
+    };
+    let end = match args.get(1) {
+        Some(end) => index(procedure, end, length + 1, length)?,
+        None => length,
+    };
+    if start > end {
+        return Err(Error::type_error(
+            procedure,
+            &format!("an end index of at least {}", start),
+            &end.to_string(),
+        ));
+    }
+    Ok((start, end))
+}
+
+fn vector_of<'a>(procedure: &str, value: &'a Value) -> Result<&'a RefCell<Vec<Value>>> {
+    match value {
+        Value::Vector(values) => Ok(values),
+        value => Err(Error::type_error(procedure, "a vector", value.type_name())),
+    }
+}
+
+fn make_vector(args: &[Value]) -> Result<Value> {
+    let length = match &args[0] {
+        Value::Int(n) if *n >= 0 => *n as usize,
+        value => return Err(Error::type_error("make-vector", "a nonnegative integer", value.type_name())),
+    };
+    let fill = args.get(1).cloned().unwrap_or(Value::Unspecified);
+    Ok(Value::vector(vec![fill; length]))
+}
+
+fn vector(args: &[Value]) -> Result<Value> {
+    Ok(Value::vector(args.to_vec()))
+}
+
+fn is_vector(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(args[0], Value::Vector(_))))
+}
+
+fn vector_length(args: &[Value]) -> Result<Value> {
+    Ok(Value::Int(vector_of("vector-length", &args[0])?.borrow().len() as i64))
+}
+
+fn vector_ref(args: &[Value]) -> Result<Value> {
+    let values = vector_of("vector-ref", &args[0])?.borrow();
+    let i = index("vector-ref", &args[1], values.len(), values.len())?;
+    Ok(values[i].clone())
+}
+
+fn vector_set(args: &[Value]) -> Result<Value> {
+    let mut values = vector_of("vector-set!", &args[0])?.borrow_mut();
+    let i = index("vector-set!", &args[1], values.len(), values.len())?;
+    values[i] = args[2].clone();
+    Ok(Value::Unspecified)
+}
+
+fn vector_to_list(args: &[Value]) -> Result<Value> {
+    let values = vector_of("vector->list", &args[0])?.borrow();
+    let (start, end) = range("vector->list", &args[1..], values.len())?;
+    Ok(Value::list(values[start..end].to_vec()))
+}
+
+fn list_to_vector(args: &[Value]) -> Result<Value> {
+    match args[0].to_vec() {
+        Some(values) => Ok(Value::vector(values)),
+        None => Err(Error::type_error("list->vector", "a list", args[0].type_name())),
+    }
+}
+
+fn vector_fill(args: &[Value]) -> Result<Value> {
+    let mut values = vector_of("vector-fill!", &args[0])?.borrow_mut();
+    let (start, end) = range("vector-fill!", &args[2..], values.len())?;
+    values[start..end].fill(args[1].clone());
+    Ok(Value::Unspecified)
+}
+
+fn string_of<'a>(procedure: &str, value: &'a Value) -> Result<&'a str> {
+    match value {
+        Value::String(s) => Ok(s),
+        value => Err(Error::type_error(procedure, "a string", value.type_name())),
+    }
+}
+
+/// The chars of `s` between the optional `start` and `end` arguments.
+fn string_range(procedure: &str, s: &str, args: &[Value]) -> Result<Vec<char>> {
+    let chars = s.chars().collect::<Vec<char>>();
+    let (start, end) = range(procedure, args, chars.len())?;
+    Ok(chars[start..end].to_vec())
+}
+
+fn is_string(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(args[0], Value::String(_))))
+}
+
+fn string_length(args: &[Value]) -> Result<Value> {
+    Ok(Value::Int(string_of("string-length", &args[0])?.chars().count() as i64))
+}
+
+fn string_ref(args: &[Value]) -> Result<Value> {
+    let s = string_of("string-ref", &args[0])?;
+    let length = s.chars().count();
+    let i = index("string-ref", &args[1], length, length)?;
+    Ok(Value::Char(s.chars().nth(i).unwrap()))
+}
+
+fn substring(args: &[Value]) -> Result<Value> {
+    let s = string_of("substring", &args[0])?;
+    let chars = string_range("substring", s, &args[1..])?;
+    Ok(Value::string(&chars.into_iter().collect::<String>()))
+}
+
+fn string_append(args: &[Value]) -> Result<Value> {
+    let mut result = String::new();
+    for value in args {
+        result.push_str(string_of("string-append", value)?);
+    }
+    Ok(Value::string(&result))
+}
+
+fn string_copy(args: &[Value]) -> Result<Value> {
+    let s = string_of("string-copy", &args[0])?;
+    let chars = string_range("string-copy", s, &args[1..])?;
+    Ok(Value::string(&chars.into_iter().collect::<String>()))
+}
+
+fn string_upcase(args: &[Value]) -> Result<Value> {
+    Ok(Value::string(&string_of("string-upcase", &args[0])?.to_uppercase()))
+}
+
+fn string_downcase(args: &[Value]) -> Result<Value> {
+    Ok(Value::string(&string_of("string-downcase", &args[0])?.to_lowercase()))
+}
+
+/// The char index where `pattern` first occurs in the string, or `#f`.
+fn string_contains(args: &[Value]) -> Result<Value> {
+    let s = string_of("string-contains", &args[0])?;
+    let pattern = string_of("string-contains", &args[1])?;
+    Ok(match s.find(pattern) {
+        Some(byte) => Value::Int(s[..byte].chars().count() as i64),
+        None => Value::Bool(false),
+    })
+}
+
+/// Splits on a char or a non-empty string, keeping empty fields.
+fn string_split(args: &[Value]) -> Result<Value> {
+    let s = string_of("string-split", &args[0])?;
+    let fields = match &args[1] {
+        Value::Char(c) => s.split(*c).map(Value::string).collect(),
+        Value::String(delimiter) if !delimiter.is_empty() => {
+            s.split(&**delimiter).map(Value::string).collect()
+        }
+        value => {
+            return Err(Error::type_error(
+                "string-split",
+                "a char or a non-empty string",
+                value.type_name(),
+            ))
+        }
+    };
+    Ok(Value::list(fields))
+}
+
+/// Joins a list of strings, separated by a space unless a delimiter is given.
+fn string_join(args: &[Value]) -> Result<Value> {
+    let strings = args[0]
+        .to_vec()
+        .ok_or_else(|| Error::type_error("string-join", "a list", args[0].type_name()))?;
+    let delimiter = match args.get(1) {
+        Some(delimiter) => string_of("string-join", delimiter)?,
+        None => " ",
+    };
+    let strings = strings
+        .iter()
+        .map(|value| string_of("string-join", value))
+        .collect::<Result<Vec<&str>>>()?;
+    Ok(Value::string(&strings.join(delimiter)))
+}
+
+fn string_to_list(args: &[Value]) -> Result<Value> {
+    let s = string_of("string->list", &args[0])?;
+    let chars = string_range("string->list", s, &args[1..])?;
+    Ok(Value::list(chars.into_iter().map(Value::Char).collect()))
+}
+
+fn list_to_string(args: &[Value]) -> Result<Value> {
+    let values = args[0]
+        .to_vec()
+        .ok_or_else(|| Error::type_error("list->string", "a list", args[0].type_name()))?;
+    let s = values
+        .iter()
+        .map(|value| char_of("list->string", value))
+        .collect::<Result<String>>()?;
+    Ok(Value::string(&s))
+}
+
+fn string_to_symbol(args: &[Value]) -> Result<Value> {
+    Ok(Value::symbol(string_of("string->symbol", &args[0])?))
+}
+
+fn symbol_to_string(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::Symbol(name) | Value::Gensym(name) => Ok(Value::string(name)),
+        value => Err(Error::type_error("symbol->string", "a symbol", value.type_name())),
+    }
+}
+
+fn is_symbol(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(args[0], Value::Symbol(_) | Value::Gensym(_))))
+}
+
+/// A fresh uninterned symbol such as `g@1`, or `prefix@1` given a string or
+/// symbol prefix. It is `eq?` to no symbol read or made by `string->symbol`.
+fn gensym(args: &[Value]) -> Result<Value> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let prefix = match args.first() {
+        None => "g",
+        Some(Value::Symbol(prefix) | Value::Gensym(prefix) | Value::String(prefix)) => prefix,
+        Some(value) => return Err(Error::type_error("gensym", "a string or symbol", value.type_name())),
+    };
+    let count = COUNT.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+    Ok(Value::Gensym(Rc::from(format!("{}@{}", prefix, count))))
+}
+
+/// Checks that `holds` is true of each adjacent pair of strings in `args`.
+fn compare_strings(procedure: &str, args: &[Value], holds: fn(&str, &str) -> bool) -> Result<Value> {
+    let strings = args
+        .iter()
+        .map(|value| string_of(procedure, value))
+        .collect::<Result<Vec<&str>>>()?;
+    Ok(Value::Bool(strings.windows(2).all(|pair| holds(pair[0], pair[1]))))
+}
+
+fn string_eq(args: &[Value]) -> Result<Value> {
+    compare_strings("string=?", args, |a, b| a == b)
+}
+
+fn string_lt(args: &[Value]) -> Result<Value> {
+    compare_strings("string<?", args, |a, b| a < b)
+}
+
+fn string_gt(args: &[Value]) -> Result<Value> {
+    compare_strings("string>?", args, |a, b| a > b)
+}
+
+fn string_le(args: &[Value]) -> Result<Value> {
+    compare_strings("string<=?", args, |a, b| a <= b)
+}
+
+fn string_ge(args: &[Value]) -> Result<Value> {
+    compare_strings("string>=?", args, |a, b| a >= b)
+}
+
+/// Makes an error object; the prelude's `error` raises it.
+fn error(args: &[Value]) -> Result<Value> {
+    Ok(Value::ErrorObject(Rc::new(ErrorObject {
+        message: args[0].to_string(),
+        irritants: args[1..].to_vec(),
+        span: None,
+    })))
+}
+
+/// Raises `args[0]` past every handler, ending the program. The prelude's
+/// `raise` calls this once no handlers are left.
+fn raise(args: &[Value]) -> Result<Value> {
+    let error = Error::Raised(args[0].clone());
+    match &args[0] {
+        Value::ErrorObject(object) => match &object.span {
+            Some(span) => Err(error.at(span)),
+            None => Err(error),
+        },
+        _ => Err(error),
+    }
+}
+
+fn error_object_of<'a>(procedure: &str, value: &'a Value) -> Result<&'a ErrorObject> {
+    match value {
+        Value::ErrorObject(object) => Ok(object),
+        value => Err(Error::type_error(procedure, "an error object", value.type_name())),
+    }
+}
+
+fn is_error_object(args: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(args[0], Value::ErrorObject(_))))
+}
+
+fn error_object_message(args: &[Value]) -> Result<Value> {
+    Ok(Value::string(&error_object_of("error-object-message", &args[0])?.message))
+}
+
+fn error_object_irritants(args: &[Value]) -> Result<Value> {
+    Ok(Value::list(error_object_of("error-object-irritants", &args[0])?.irritants.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::eval;
+
+    #[test]
+    fn pairs_and_quoted_lists() {
+        assert_eq!(eval("(cons 1 (cons 2 '()))").unwrap(), "(1 2)");
+        assert_eq!(eval("'(a . b)").unwrap(), "(a . b)");
+        assert_eq!(eval("(quote (1 (2 \"three\") . 4))").unwrap(), "(1 (2 \"three\") . 4)");
+        assert_eq!(eval("(list (car '(1 2)) (cdr '(1 2)) (null? '()) (pair? '()))").unwrap(), "(1 (2) #t #f)");
+        assert_eq!(eval("(define p (list 1 2)) (set-car! p 'x) (set-cdr! (cdr p) '(3)) p").unwrap(), "(x 2 3)");
+    }
+
+    #[test]
+    fn vectors_and_their_literals() {
+        assert_eq!(eval("#(1 \"two\" #(3))").unwrap(), "#(1 \"two\" #(3))");
+        assert_eq!(eval("(define v (make-vector 3 0)) (vector-set! v 1 'x) v").unwrap(), "#(0 x 0)");
+        assert_eq!(eval("(vector->list (vector-map + #(1 2 3) #(10 20)))").unwrap(), "(11 22)");
+        assert_eq!(eval("(define v (vector 1 2)) (vector-fill! v 7) (list->vector (cons (vector-length v) (vector->list v)))").unwrap(), "#(2 7 7)");
+        assert_eq!(eval("(apply + 1 2 '(3 4))").unwrap(), "10");
+    }
+
+    #[test]
+    fn vector_indexes_are_bounds_checked() {
+        let error = eval("(vector-ref #(1 2) 2)").unwrap_err();
+        assert!(error.to_string().starts_with("vector-ref: index 2 is out of range for length 2"), "{}", error);
+    }
+
+    #[test]
+    fn string_library() {
+        assert_eq!(eval("(list (string-length \"λx\") (string-ref \"λx\" 0) (substring \"hello\" 1 3))").unwrap(), "(2 #\\λ \"el\")");
+        assert_eq!(eval("(string-append \"a\" (string-upcase \"b\") (string-downcase \"C\") (string-copy \"def\" 1))").unwrap(), "\"aBcef\"");
+        assert_eq!(eval("(list (string-index \"hello\" #\\l) (string-contains \"hello\" \"lo\") (string-contains \"hello\" \"x\"))").unwrap(), "(2 3 #f)");
+        assert_eq!(eval("(string-join (string-split \"a,b,,c\" #\\,) \"-\")").unwrap(), "\"a-b--c\"");
+        assert_eq!(eval("(list->string (cdr (string->list \"abc\")))").unwrap(), "\"bc\"");
+        assert_eq!(eval("(symbol->string (string->symbol \"name\"))").unwrap(), "\"name\"");
+        assert_eq!(eval("(list (string=? \"a\" \"a\" \"a\") (string<? \"a\" \"b\") (string>=? \"a\" \"b\"))").unwrap(), "(#t #t #f)");
+    }
+
+    #[test]
+    fn gensyms_are_uninterned() {
+        assert_eq!(eval("(define g (gensym)) (list (symbol? g) (eq? g g) (eq? g (gensym)))").unwrap(), "(#t #t #f)");
+        assert_eq!(eval("(define g (gensym)) (eq? g (string->symbol (symbol->string g)))").unwrap(), "#f");
+        assert!(eval("(symbol->string (gensym 'tmp))").unwrap().starts_with("\"tmp@"));
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::span::Span;
+use crate::value::Value;
+
+/// One VM instruction. Every expression leaves exactly one value on the stack.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    /// Pushes `constants[index]`.
+    Constant(usize),
+    Unspecified,
+    Pop,
+    GetGlobal(usize),
+    SetGlobal(usize),
+    DefineGlobal(usize),
+    /// `(depth, slot)`: how many frames outwards, and which slot in that frame.
+    GetLocal(usize, usize),
+    SetLocal(usize, usize),
+    /// Pushes a copy of the top of the stack.
+    Dup,
+    /// Exchanges the top two values.
+    Swap,
+    Jump(usize),
+    JumpIfFalse(usize),
+    /// Jumps, keeping the value, if the top of the stack is false; pops it otherwise.
+    JumpIfFalseOrPop(usize),
+    /// Jumps, keeping the value, if the top of the stack is true; pops it otherwise.
+    JumpIfTrueOrPop(usize),
+    /// `(constant, target)`: jumps unless the top of the stack is `eqv?` to an
+    /// element of the vector `constants[constant]`. Used by `case`.
+    JumpUnlessMember(usize, usize),
+    /// Closes `functions[index]` over the current frame.
+    Closure(usize),
+    /// Calls the procedure below the given number of arguments.
+    Call(usize),
+    /// A call in tail position: a procedure call replaces the current frame
+    /// instead of growing the frame stack.
+    TailCall(usize),
+    Return,
+    /// Enters a block scope with the given number of slots.
+    PushScope(usize),
+    PopScope,
+}
+
+/// A compiled procedure body, or a top-level form.
+pub struct Code {
+    pub name: Option<String>,
+    pub params: usize,
+    pub rest: bool,
+    /// Slots in the frame a call allocates: parameters first, then internal defines.
+    pub frame_size: usize,
+    pub ops: Vec<Op>,
+    /// The source location of each op, for error reporting.
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    pub functions: Vec<Rc<Code>>,
+}
+
+impl Code {
+    pub fn new(name: Option<String>) -> Self {
+        Self {
+            name,
+            params: 0,
+            rest: false,
+            frame_size: 0,
+            ops: Vec::new(),
+            spans: Vec::new(),
+            constants: Vec::new(),
+            functions: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "#<code {}>", name),
+            None => write!(f, "#<code>"),
+        }
+    }
+}
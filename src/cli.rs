@@ -1,14 +1,114 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use crate::error::{Error, Result};
+
+pub const USAGE: &str = "usage: scheme-compilator [program.scm | repl]
+       scheme-compilator compile program.scm [-o program] [-S]
+       scheme-compilator emit-c program.scm [-o program.c]";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(PathBuf),
+    Repl,
+    /// `compile <path> [-o <output>] [-S]`; `-S` stops after writing assembly.
+    Compile {
+        path: PathBuf,
+        output: Option<PathBuf>,
+        assembly_only: bool,
+    },
+    /// `emit-c <path> [-o <output>]`
+    EmitC {
+        path: PathBuf,
+        output: Option<PathBuf>,
+    },
+}
 
 pub struct Cli{
-    pub path: std::path::PathBuf,
+    pub command: Command,
 }
 
 impl Cli{
-    pub fn new() -> Self{
-        let mut args = std::env::args_os();
-        let path = args.nth(1).expect("Missing path argument");
-        Self{
-            path: std::path::PathBuf::from(path),
-        }   
-    }
-}
\ No newline at end of file
+    pub fn new() -> Result<Self>{
+        Self::parse(std::env::args_os().skip(1))
+    }
+
+    /// Parses the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Self>{
+        let mut args = args.into_iter();
+        let command = match args.next() {
+            None => Command::Repl,
+            Some(arg) if arg == "repl" => Command::Repl,
+            Some(arg) if arg == "compile" || arg == "emit-c" => {
+                let compile = arg == "compile";
+                let mut path = None;
+                let mut output = None;
+                let mut assembly_only = false;
+                while let Some(arg) = args.next() {
+                    if arg == "-o" {
+                        let file = args.next().ok_or_else(|| Error::Usage("missing output path after -o".to_string()))?;
+                        output = Some(PathBuf::from(file));
+                    } else if arg == "-S" && compile {
+                        assembly_only = true;
+                    } else if arg.to_string_lossy().starts_with('-') {
+                        return Err(Error::Usage(format!("unknown option '{}'", arg.to_string_lossy())));
+                    } else if path.is_none() {
+                        path = Some(PathBuf::from(arg));
+                    } else {
+                        return Err(Error::Usage(format!("unexpected argument '{}'", arg.to_string_lossy())));
+                    }
+                }
+                let path = path.ok_or_else(|| Error::Usage("missing path argument".to_string()))?;
+                match compile {
+                    true => Command::Compile {
+                        path,
+                        output,
+                        assembly_only,
+                    },
+                    false => Command::EmitC { path, output },
+                }
+            }
+            Some(path) => Command::Run(PathBuf::from(path)),
+        };
+        if let Some(arg) = args.next() {
+            return Err(Error::Usage(format!("unexpected argument '{}'", arg.to_string_lossy())));
+        }
+        Ok(Self{
+            command,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command> {
+        Cli::parse(args.iter().map(OsString::from)).map(|cli| cli.command)
+    }
+
+    #[test]
+    fn commands_and_options_are_recognised() {
+        assert_eq!(parse(&[]).unwrap(), Command::Repl);
+        assert_eq!(parse(&["prog.scm"]).unwrap(), Command::Run(PathBuf::from("prog.scm")));
+        assert_eq!(
+            parse(&["compile", "-S", "prog.scm", "-o", "out.s"]).unwrap(),
+            Command::Compile {
+                path: PathBuf::from("prog.scm"),
+                output: Some(PathBuf::from("out.s")),
+                assembly_only: true,
+            }
+        );
+    }
+
+    #[test]
+    fn bad_command_lines_are_errors() {
+        let message = |args: &[&str]| parse(args).unwrap_err().to_string();
+        assert_eq!(message(&["compile"]), "missing path argument");
+        assert_eq!(message(&["compile", "prog.scm", "-o"]), "missing output path after -o");
+        assert_eq!(message(&["emit-c", "prog.scm", "-S"]), "unknown option '-S'");
+        assert_eq!(message(&["emit-c", "a.scm", "b.scm"]), "unexpected argument 'b.scm'");
+        assert_eq!(message(&["a.scm", "b.scm"]), "unexpected argument 'b.scm'");
+        assert_eq!(message(&["repl", "now"]), "unexpected argument 'now'");
+    }
+}
@@ -0,0 +1,809 @@
+use std::rc::Rc;
+
+use crate::bytecode::{Code, Op};
+use crate::environment::Globals;
+use crate::error::{Error, Result};
+use crate::expander;
+use crate::parser::{Argument, Expression};
+use crate::span::Span;
+use crate::value::Value;
+
+/// Lowers parsed expressions to bytecode. Locals are resolved to frame slots
+/// at compile time; anything not found in an enclosing scope is a global.
+pub struct Compiler<'a> {
+    globals: &'a mut Globals,
+    /// The code objects being built, innermost last.
+    codes: Vec<Code>,
+    /// The names bound by each runtime frame in scope, innermost last.
+    scopes: Vec<Vec<String>>,
+}
+
+pub fn symbol_name(arg: &Argument) -> Option<&str> {
+    match arg {
+        Argument::LiteralVariable(literal) => match &literal.value {
+            Value::Symbol(name) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn bad_syntax(form: &str) -> Error {
+    Error::Parse(format!("{}: bad syntax", form))
+}
+
+/// Checks the operand count of a special form.
+pub fn expect_args(form: &str, args: &[Argument], min: usize, max: Option<usize>) -> Result<()> {
+    if args.len() < min || max.is_some_and(|max| args.len() > max) {
+        return Err(bad_syntax(form));
+    }
+    Ok(())
+}
+
+/// The elements of a parenthesized form such as a parameter or binding list.
+pub fn list_elements(arg: &Argument) -> Option<Vec<&Argument>> {
+    match arg {
+        Argument::LiteralVariable(literal) if matches!(literal.value, Value::Nil) => Some(Vec::new()),
+        Argument::Expression(expr) if expr.rest.is_none() => {
+            Some(std::iter::once(expr.function.as_ref()).chain(&expr.arguments).collect())
+        }
+        _ => None,
+    }
+}
+
+/// The `(variable init)` pairs of a `let`-style binding list.
+fn bindings<'b>(form: &str, arg: &'b Argument) -> Result<Vec<(&'b Argument, &'b Argument)>> {
+    list_elements(arg)
+        .ok_or_else(|| bad_syntax(form))?
+        .into_iter()
+        .map(|binding| match binding {
+            Argument::Expression(binding)
+                if binding.rest.is_none()
+                    && binding.arguments.len() == 1
+                    && symbol_name(&binding.function).is_some() =>
+            {
+                Ok((binding.function.as_ref(), &binding.arguments[0]))
+            }
+            _ => Err(bad_syntax(form)),
+        })
+        .collect()
+}
+
+/// The names bound by `bindings`, which must all differ.
+fn binding_names(form: &str, bindings: &[(&Argument, &Argument)]) -> Result<Vec<String>> {
+    let mut names: Vec<String> = Vec::new();
+    for (variable, _) in bindings {
+        let name = symbol_name(variable).unwrap();
+        if names.iter().any(|bound| bound == name) {
+            return Err(Error::Parse(format!("{}: duplicate binding '{}'", form, name)));
+        }
+        names.push(name.to_string());
+    }
+    Ok(names)
+}
+
+/// The name bound by `(define name ...)` or `(define (name ...) ...)`.
+pub fn define_target(args: &[Argument]) -> Option<&str> {
+    match args.first()? {
+        Argument::Expression(signature) => symbol_name(&signature.function),
+        target => symbol_name(target),
+    }
+}
+
+/// The name bound by a `define` form, if `arg` is one.
+pub fn defined_name(arg: &Argument) -> Option<&str> {
+    match arg {
+        Argument::Expression(expr) if symbol_name(&expr.function) == Some("define") => {
+            define_target(&expr.arguments)
+        }
+        _ => None,
+    }
+}
+
+/// The forms of a body, with those of any `begin` in it spliced in place.
+fn body_forms(body: &[Argument]) -> Vec<&Argument> {
+    let mut forms = Vec::new();
+    for arg in body {
+        match arg {
+            Argument::Expression(expr) if symbol_name(&expr.function) == Some("begin") => {
+                forms.extend(body_forms(&expr.arguments));
+            }
+            _ => forms.push(arg),
+        }
+    }
+    forms
+}
+
+impl<'a> Compiler<'a> {
+    /// Compiles one top-level form into code that takes no arguments.
+    pub fn compile(globals: &'a mut Globals, arg: &Argument) -> Result<Rc<Code>> {
+        let mut compiler = Self {
+            globals,
+            codes: vec![Code::new(None)],
+            scopes: Vec::new(),
+        };
+        // Not a tail call: the top-level frame stays on the stack, so errors
+        // raised inside library procedures can be reported at this form.
+        compiler.expression(arg, false)?;
+        compiler.emit(Op::Return, arg.span());
+        Ok(Rc::new(compiler.codes.pop().unwrap()))
+    }
+
+    fn code(&mut self) -> &mut Code {
+        self.codes.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op, span: &Span) -> usize {
+        let code = self.code();
+        code.ops.push(op);
+        code.spans.push(span.clone());
+        code.ops.len() - 1
+    }
+
+    /// Points the jump at `index` to the next op to be emitted.
+    fn patch(&mut self, index: usize) {
+        let target = self.code().ops.len();
+        match &mut self.code().ops[index] {
+            Op::Jump(to)
+            | Op::JumpIfFalse(to)
+            | Op::JumpIfFalseOrPop(to)
+            | Op::JumpIfTrueOrPop(to)
+            | Op::JumpUnlessMember(_, to) => *to = target,
+            _ => unreachable!("patching a non-jump"),
+        }
+    }
+
+    fn constant_index(&mut self, value: Value) -> usize {
+        let constants = &mut self.code().constants;
+        constants.push(value);
+        constants.len() - 1
+    }
+
+    fn constant(&mut self, value: Value, span: &Span) {
+        let index = self.constant_index(value);
+        self.emit(Op::Constant(index), span);
+    }
+
+    /// The slot of `name` in the innermost scope, allocating one if needed.
+    fn declare(&mut self, name: &str) -> usize {
+        let scope = self.scopes.last_mut().unwrap();
+        match scope.iter().position(|bound| bound == name) {
+            Some(slot) => slot,
+            None => {
+                scope.push(name.to_string());
+                scope.len() - 1
+            }
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Option<(usize, usize)> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| Some((depth, scope.iter().position(|bound| bound == name)?)))
+    }
+
+    /// Compiles `arg` to push its value. A `tail` expression is the last thing
+    /// its procedure evaluates, so calls there reuse the caller's frame.
+    fn expression(&mut self, arg: &Argument, tail: bool) -> Result<()> {
+        match arg {
+            Argument::Expression(expr) => match symbol_name(&expr.function) {
+                // keywords
+                Some("begin") => self.begin(expr, tail),
+                Some("quote") => self.quote(expr),
+                Some("define") => self.define(expr),
+                Some("set!") => self.set(expr),
+                Some("lambda") => self.lambda_expression(expr, None),
+                Some("let") => self.let_expression(expr, tail),
+                Some("let*") => self.let_star(expr, tail),
+                Some("letrec") | Some("letrec*") => self.letrec(expr, tail),
+                Some("while") => self.while_statement(expr),
+                Some("if") => self.if_statement(expr, tail),
+                Some("cond") => self.cond(expr, tail),
+                Some("case") => self.case(expr, tail),
+                Some("when") | Some("unless") => self.when_unless(expr, tail),
+                Some("and") => self.short_circuit(expr, tail, true),
+                Some("or") => self.short_circuit(expr, tail, false),
+                // procedures
+                _ => self.call(expr, tail),
+            }
+            .map_err(|error| error.at(&expr.span)),
+            Argument::LiteralVariable(literal) => {
+                match &literal.value {
+                    Value::Symbol(name) => match self.resolve(name) {
+                        Some((depth, slot)) => {
+                            self.emit(Op::GetLocal(depth, slot), &literal.span);
+                        }
+                        None => {
+                            let index = self.globals.index(name);
+                            self.emit(Op::GetGlobal(index), &literal.span);
+                        }
+                    },
+                    value => self.constant(value.clone(), &literal.span),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Compiles `arg` for its side effects only, leaving nothing on the stack.
+    fn effect(&mut self, arg: &Argument) -> Result<()> {
+        if let Argument::Expression(expr) = arg {
+            let compiled = match symbol_name(&expr.function) {
+                Some("define") => Some(self.definition(expr)),
+                Some("set!") => Some(self.assignment(expr)),
+                _ => None,
+            };
+            if let Some(compiled) = compiled {
+                return compiled.map_err(|error| error.at(&expr.span));
+            }
+        }
+        self.expression(arg, false)?;
+        self.emit(Op::Pop, arg.span());
+        Ok(())
+    }
+
+    /// Compiles a sequence, keeping only the value of the last form.
+    fn body(&mut self, body: &[Argument], span: &Span, tail: bool) -> Result<()> {
+        match body_forms(body).split_last() {
+            Some((last, init)) => {
+                for &arg in init {
+                    self.effect(arg)?;
+                }
+                self.expression(last, tail)
+            }
+            None => {
+                self.emit(Op::Unspecified, span);
+                Ok(())
+            }
+        }
+    }
+
+    /// Compiles `inner` in a new runtime frame that binds `names`, followed by
+    /// whatever `body` defines.
+    fn scoped(
+        &mut self,
+        names: Vec<String>,
+        body: &[Argument],
+        span: &Span,
+        inner: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let enter = self.emit(Op::PushScope(0), span);
+        self.scopes.push(names);
+        for name in body_forms(body).into_iter().filter_map(defined_name) {
+            self.declare(name);
+        }
+        // A tail call leaves the frame, scope and all, so the PopScope is skipped.
+        inner(self)?;
+        self.emit(Op::PopScope, span);
+        let size = self.scopes.pop().unwrap().len();
+        self.code().ops[enter] = Op::PushScope(size);
+        Ok(())
+    }
+
+    /// A nested `begin` that defines anything gets a frame of its own.
+    fn begin(&mut self, expr: &Expression, tail: bool) -> Result<()> {
+        let body = &expr.arguments;
+        if !body_forms(body).into_iter().any(|arg| defined_name(arg).is_some()) {
+            return self.body(body, &expr.span, tail);
+        }
+        self.scoped(Vec::new(), body, &expr.span, |this| this.body(body, &expr.span, tail))
+    }
+
+    /// `(let ((name init) ...) body...)`: the inits are evaluated in the
+    /// enclosing scope, then bound in a new frame.
+    fn let_expression(&mut self, expr: &Expression, tail: bool) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("let", args, 2, None)?;
+        if let Some(name) = symbol_name(&args[0]) {
+            return self.named_let(expr, name, tail);
+        }
+        let bindings = bindings("let", &args[0])?;
+        let names = binding_names("let", &bindings)?;
+        for (name, (_, init)) in names.iter().zip(&bindings) {
+            self.named_value(name, init)?;
+        }
+        self.scoped(names, &args[1..], &expr.span, |this| {
+            for slot in (0..bindings.len()).rev() {
+                this.emit(Op::SetLocal(0, slot), &expr.span);
+            }
+            this.body(&args[1..], &expr.span, tail)
+        })
+    }
+
+    /// `(let name ((var init) ...) body...)` calls a local procedure `name`
+    /// that the body can call again to loop.
+    fn named_let(&mut self, expr: &Expression, name: &str, tail: bool) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("let", args, 3, None)?;
+        let bindings = bindings("let", &args[1])?;
+        binding_names("let", &bindings)?;
+        let params = bindings
+            .iter()
+            .map(|(variable, _)| (*variable).clone())
+            .collect::<Vec<Argument>>();
+        self.scoped(vec![name.to_string()], &[], &expr.span, |this| {
+            this.lambda(Some(name.to_string()), &params, None, &args[2..], &expr.span)?;
+            this.emit(Op::SetLocal(0, 0), &expr.span);
+            this.emit(Op::GetLocal(0, 0), &expr.span);
+            Ok(())
+        })?;
+        for (_, init) in &bindings {
+            self.expression(init, false)?;
+        }
+        let argc = bindings.len();
+        self.emit(if tail { Op::TailCall(argc) } else { Op::Call(argc) }, &expr.span);
+        Ok(())
+    }
+
+    /// `let*` binds one name at a time, each in a frame nested in the last.
+    fn let_star(&mut self, expr: &Expression, tail: bool) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("let*", args, 2, None)?;
+        let bindings = bindings("let*", &args[0])?;
+        self.nested_bindings(&bindings, &args[1..], &expr.span, tail)
+    }
+
+    fn nested_bindings(
+        &mut self,
+        bindings: &[(&Argument, &Argument)],
+        body: &[Argument],
+        span: &Span,
+        tail: bool,
+    ) -> Result<()> {
+        match bindings.split_first() {
+            None => self.scoped(Vec::new(), body, span, |this| this.body(body, span, tail)),
+            Some(((variable, init), rest)) => {
+                let name = symbol_name(variable).unwrap().to_string();
+                self.named_value(&name, init)?;
+                let inner = if rest.is_empty() { body } else { &[] };
+                self.scoped(vec![name], inner, span, |this| {
+                    this.emit(Op::SetLocal(0, 0), span);
+                    match rest.is_empty() {
+                        true => this.body(body, span, tail),
+                        false => this.nested_bindings(rest, body, span, tail),
+                    }
+                })
+            }
+        }
+    }
+
+    /// `letrec` and `letrec*` bind every name before evaluating the inits, in
+    /// order, so they can refer to each other.
+    fn letrec(&mut self, expr: &Expression, tail: bool) -> Result<()> {
+        let form = symbol_name(&expr.function).unwrap_or("letrec");
+        let args = &expr.arguments;
+        expect_args(form, args, 2, None)?;
+        let bindings = bindings(form, &args[0])?;
+        let names = binding_names(form, &bindings)?;
+        self.scoped(names, &args[1..], &expr.span, |this| {
+            for (slot, (variable, init)) in bindings.iter().enumerate() {
+                this.named_value(symbol_name(variable).unwrap(), init)?;
+                this.emit(Op::SetLocal(0, slot), &expr.span);
+            }
+            this.body(&args[1..], &expr.span, tail)
+        })
+    }
+
+    fn quote(&mut self, expr: &Expression) -> Result<()> {
+        expect_args("quote", &expr.arguments, 1, Some(1))?;
+        if expr.rest.is_some() {
+            return Err(bad_syntax("quote"));
+        }
+        self.constant(expr.arguments[0].to_datum(), &expr.span);
+        Ok(())
+    }
+
+    fn define(&mut self, expr: &Expression) -> Result<()> {
+        self.definition(expr)?;
+        self.emit(Op::Unspecified, &expr.span);
+        Ok(())
+    }
+
+    /// `define` without its (unspecified) result.
+    fn definition(&mut self, expr: &Expression) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("define", args, 1, None)?;
+        let name = define_target(args).ok_or_else(|| bad_syntax("define"))?;
+        // Declared before the value is compiled, so local procedures can recurse.
+        let slot = match self.scopes.is_empty() {
+            true => None,
+            false => Some(self.declare(name)),
+        };
+
+        match &args[0] {
+            // (define (name . params) body...)
+            Argument::Expression(signature) => self.lambda(
+                Some(name.to_string()),
+                &signature.arguments,
+                signature.rest.as_deref(),
+                &args[1..],
+                &expr.span,
+            )?,
+            _ => {
+                expect_args("define", args, 2, Some(2))?;
+                self.named_value(name, &args[1])?;
+            }
+        }
+
+        match slot {
+            Some(slot) => self.emit(Op::SetLocal(0, slot), &expr.span),
+            None => {
+                let index = self.globals.index(name);
+                self.emit(Op::DefineGlobal(index), &expr.span)
+            }
+        };
+        Ok(())
+    }
+
+    /// Compiles the value bound to `name`, giving an anonymous lambda that name.
+    fn named_value(&mut self, name: &str, value: &Argument) -> Result<()> {
+        match value {
+            Argument::Expression(lambda) if symbol_name(&lambda.function) == Some("lambda") => self
+                .lambda_expression(lambda, Some(name.to_string()))
+                .map_err(|error| error.at(&lambda.span)),
+            value => self.expression(value, false),
+        }
+    }
+
+    fn lambda(
+        &mut self,
+        name: Option<String>,
+        params: &[Argument],
+        rest: Option<&Argument>,
+        body: &[Argument],
+        span: &Span,
+    ) -> Result<()> {
+        if body.is_empty() {
+            return Err(bad_syntax("lambda"));
+        }
+        let mut names = params
+            .iter()
+            .map(|param| match symbol_name(param) {
+                Some(name) => Ok(name.to_string()),
+                None => Err(bad_syntax("lambda")),
+            })
+            .collect::<Result<Vec<String>>>()?;
+        let mut code = Code::new(name.map(|name| expander::base_name(&name).to_string()));
+        code.params = names.len();
+        if let Some(rest) = rest {
+            names.push(symbol_name(rest).ok_or_else(|| bad_syntax("lambda"))?.to_string());
+            code.rest = true;
+        }
+
+        self.codes.push(code);
+        self.scopes.push(names);
+        for name in body_forms(body).into_iter().filter_map(defined_name) {
+            self.declare(name);
+        }
+        self.body(body, span, true)?;
+        self.emit(Op::Return, span);
+        let mut code = self.codes.pop().unwrap();
+        code.frame_size = self.scopes.pop().unwrap().len();
+
+        let functions = &mut self.code().functions;
+        functions.push(Rc::new(code));
+        let index = functions.len() - 1;
+        self.emit(Op::Closure(index), span);
+        Ok(())
+    }
+
+    fn lambda_expression(&mut self, expr: &Expression, name: Option<String>) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("lambda", args, 2, None)?;
+        match &args[0] {
+            // (lambda (a b . rest) body...)
+            Argument::Expression(params) => {
+                let mut names = vec![params.function.as_ref().clone()];
+                names.extend(params.arguments.iter().cloned());
+                self.lambda(name, &names, params.rest.as_deref(), &args[1..], &expr.span)
+            }
+            // (lambda () body...)
+            Argument::LiteralVariable(literal) if matches!(literal.value, Value::Nil) => {
+                self.lambda(name, &[], None, &args[1..], &expr.span)
+            }
+            // (lambda args body...)
+            rest => self.lambda(name, &[], Some(rest), &args[1..], &expr.span),
+        }
+    }
+
+    fn set(&mut self, expr: &Expression) -> Result<()> {
+        self.assignment(expr)?;
+        self.emit(Op::Unspecified, &expr.span);
+        Ok(())
+    }
+
+    /// `set!` without its (unspecified) result.
+    fn assignment(&mut self, expr: &Expression) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("set!", args, 2, Some(2))?;
+        let name = symbol_name(&args[0]).ok_or_else(|| bad_syntax("set!"))?;
+        self.expression(&args[1], false)?;
+        match self.resolve(name) {
+            Some((depth, slot)) => self.emit(Op::SetLocal(depth, slot), args[0].span()),
+            None => {
+                let index = self.globals.index(name);
+                self.emit(Op::SetGlobal(index), args[0].span())
+            }
+        };
+        Ok(())
+    }
+
+    fn while_statement(&mut self, expr: &Expression) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("while", args, 1, None)?;
+        let start = self.code().ops.len();
+        self.expression(&args[0], false)?;
+        let exit = self.emit(Op::JumpIfFalse(0), &expr.span);
+        for arg in &args[1..] {
+            self.effect(arg)?;
+        }
+        self.emit(Op::Jump(start), &expr.span);
+        self.patch(exit);
+        self.emit(Op::Unspecified, &expr.span);
+        Ok(())
+    }
+
+    fn if_statement(&mut self, expr: &Expression, tail: bool) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("if", args, 2, Some(3))?;
+        self.expression(&args[0], false)?;
+        let otherwise = self.emit(Op::JumpIfFalse(0), &expr.span);
+        self.expression(&args[1], tail)?;
+        let end = self.emit(Op::Jump(0), &expr.span);
+        self.patch(otherwise);
+        match args.get(2) {
+            Some(arg) => self.expression(arg, tail)?,
+            None => {
+                self.emit(Op::Unspecified, &expr.span);
+            }
+        }
+        self.patch(end);
+        Ok(())
+    }
+
+    /// `(cond (test body...) (test => receiver) (test) ... (else body...))`
+    fn cond(&mut self, expr: &Expression, tail: bool) -> Result<()> {
+        let clauses = &expr.arguments;
+        let mut exits = Vec::new();
+        let mut has_else = false;
+        for (i, clause) in clauses.iter().enumerate() {
+            let clause = match clause {
+                Argument::Expression(clause) if clause.rest.is_none() => clause,
+                _ => return Err(bad_syntax("cond")),
+            };
+            let body = &clause.arguments;
+            if symbol_name(&clause.function) == Some("else") {
+                if i + 1 != clauses.len() || body.is_empty() {
+                    return Err(bad_syntax("cond"));
+                }
+                self.body(body, &clause.span, tail)?;
+                has_else = true;
+                break;
+            }
+
+            self.expression(&clause.function, false)?;
+            match body.first().and_then(symbol_name) {
+                // (test): the value of the test itself
+                _ if body.is_empty() => exits.push(self.emit(Op::JumpIfTrueOrPop(0), &clause.span)),
+                // (test => receiver): the receiver is called with the test's value
+                Some("=>") => {
+                    if body.len() != 2 {
+                        return Err(bad_syntax("cond"));
+                    }
+                    self.emit(Op::Dup, &clause.span);
+                    let next = self.emit(Op::JumpIfFalse(0), &clause.span);
+                    self.expression(&body[1], false)?;
+                    self.emit(Op::Swap, &clause.span);
+                    self.emit(if tail { Op::TailCall(1) } else { Op::Call(1) }, &clause.span);
+                    exits.push(self.emit(Op::Jump(0), &clause.span));
+                    self.patch(next);
+                    self.emit(Op::Pop, &clause.span);
+                }
+                _ => {
+                    let next = self.emit(Op::JumpIfFalse(0), &clause.span);
+                    self.body(body, &clause.span, tail)?;
+                    exits.push(self.emit(Op::Jump(0), &clause.span));
+                    self.patch(next);
+                }
+            }
+        }
+        if !has_else {
+            self.emit(Op::Unspecified, &expr.span);
+        }
+        for exit in exits {
+            self.patch(exit);
+        }
+        Ok(())
+    }
+
+    /// `(case key ((datum...) body...) ((datum...) => receiver) ... (else body...))`
+    /// The key stays on the stack until a clause is chosen.
+    fn case(&mut self, expr: &Expression, tail: bool) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("case", args, 1, None)?;
+        self.expression(&args[0], false)?;
+        let clauses = &args[1..];
+        let mut exits = Vec::new();
+        let mut has_else = false;
+        for (i, clause) in clauses.iter().enumerate() {
+            let clause = match clause {
+                Argument::Expression(clause) if clause.rest.is_none() && !clause.arguments.is_empty() => clause,
+                _ => return Err(bad_syntax("case")),
+            };
+            let next = match symbol_name(&clause.function) {
+                Some("else") if i + 1 == clauses.len() => {
+                    has_else = true;
+                    None
+                }
+                _ => {
+                    let data = list_elements(&clause.function).ok_or_else(|| bad_syntax("case"))?;
+                    let data = Value::vector(data.into_iter().map(Argument::to_datum).collect());
+                    let index = self.constant_index(data);
+                    Some(self.emit(Op::JumpUnlessMember(index, 0), &clause.span))
+                }
+            };
+
+            let body = &clause.arguments;
+            match symbol_name(&body[0]) {
+                Some("=>") => {
+                    if body.len() != 2 {
+                        return Err(bad_syntax("case"));
+                    }
+                    self.expression(&body[1], false)?;
+                    self.emit(Op::Swap, &clause.span);
+                    self.emit(if tail { Op::TailCall(1) } else { Op::Call(1) }, &clause.span);
+                }
+                _ => {
+                    self.emit(Op::Pop, &clause.span);
+                    self.body(body, &clause.span, tail)?;
+                }
+            }
+            if let Some(next) = next {
+                exits.push(self.emit(Op::Jump(0), &clause.span));
+                self.patch(next);
+            }
+        }
+        if !has_else {
+            self.emit(Op::Pop, &expr.span);
+            self.emit(Op::Unspecified, &expr.span);
+        }
+        for exit in exits {
+            self.patch(exit);
+        }
+        Ok(())
+    }
+
+    fn when_unless(&mut self, expr: &Expression, tail: bool) -> Result<()> {
+        let when = symbol_name(&expr.function) == Some("when");
+        let args = &expr.arguments;
+        expect_args(if when { "when" } else { "unless" }, args, 2, None)?;
+        self.expression(&args[0], false)?;
+        let otherwise = self.emit(Op::JumpIfFalse(0), &expr.span);
+        match when {
+            true => self.body(&args[1..], &expr.span, tail)?,
+            false => {
+                self.emit(Op::Unspecified, &expr.span);
+            }
+        }
+        let end = self.emit(Op::Jump(0), &expr.span);
+        self.patch(otherwise);
+        match when {
+            true => {
+                self.emit(Op::Unspecified, &expr.span);
+            }
+            false => self.body(&args[1..], &expr.span, tail)?,
+        }
+        self.patch(end);
+        Ok(())
+    }
+
+    /// `and` stops at the first false value, `or` at the first true one; either
+    /// way the deciding value is the result.
+    fn short_circuit(&mut self, expr: &Expression, tail: bool, and: bool) -> Result<()> {
+        let Some((last, init)) = expr.arguments.split_last() else {
+            self.constant(Value::Bool(and), &expr.span);
+            return Ok(());
+        };
+        let mut exits = Vec::new();
+        for arg in init {
+            self.expression(arg, false)?;
+            let jump = match and {
+                true => Op::JumpIfFalseOrPop(0),
+                false => Op::JumpIfTrueOrPop(0),
+            };
+            exits.push(self.emit(jump, &expr.span));
+        }
+        self.expression(last, tail)?;
+        for exit in exits {
+            self.patch(exit);
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, expr: &Expression, tail: bool) -> Result<()> {
+        if expr.rest.is_some() {
+            return Err(bad_syntax("application"));
+        }
+        self.expression(&expr.function, false)?;
+        for arg in &expr.arguments {
+            self.expression(arg, false)?;
+        }
+        let argc = expr.arguments.len();
+        self.emit(if tail { Op::TailCall(argc) } else { Op::Call(argc) }, &expr.span);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::eval;
+
+    #[test]
+    fn procedures_are_first_class() {
+        assert_eq!(eval("((lambda (x y) (* x y)) 6 7)").unwrap(), "42");
+        assert_eq!(eval("(define (square x) (* x x)) (square 9)").unwrap(), "81");
+        assert_eq!(eval("((if #f + -) 10 3)").unwrap(), "7");
+        assert_eq!(eval("(define (twice f x) (f (f x))) (twice (lambda (n) (+ n 5)) 1)").unwrap(), "11");
+    }
+
+    #[test]
+    fn closures_capture_their_environment() {
+        let source = "(define (counter)
+                        (define n 0)
+                        (lambda () (set! n (+ n 1)) n))
+                      (define a (counter))
+                      (define b (counter))
+                      (a) (a) (b)
+                      (list (a) (b))";
+        assert_eq!(eval(source).unwrap(), "(3 2)");
+    }
+
+    #[test]
+    fn rest_parameters_collect_extra_arguments() {
+        assert_eq!(eval("((lambda (a . rest) (list a rest)) 1 2 3)").unwrap(), "(1 (2 3))");
+        assert_eq!(eval("((lambda args args))").unwrap(), "()");
+        assert_eq!(eval("(define (f a b . c) c) (f 1 2)").unwrap(), "()");
+    }
+
+    #[test]
+    fn calling_with_the_wrong_arguments_is_an_error() {
+        let error = eval("(define (f x) x) (f 1 2)").unwrap_err();
+        assert!(error.to_string().starts_with("f: expected 1 arguments, got 2"), "{}", error);
+        let error = eval("(5 1)").unwrap_err();
+        assert!(error.to_string().starts_with("apply: expected a procedure, got integer"), "{}", error);
+    }
+
+    #[test]
+    fn let_forms_scope_their_bindings() {
+        assert_eq!(eval("(define x 1) (let ((x 2) (y x)) (list x y))").unwrap(), "(2 1)");
+        assert_eq!(eval("(let* ((x 1) (y (+ x 1))) (list x y))").unwrap(), "(1 2)");
+        let source = "(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                               (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+                        (even? 10))";
+        assert_eq!(eval(source).unwrap(), "#t");
+        assert_eq!(eval("(letrec* ((a 1) (b (+ a 1))) b)").unwrap(), "2");
+        assert_eq!(eval("(let loop ((i 0) (acc '())) (if (= i 3) acc (loop (+ i 1) (cons i acc))))").unwrap(), "(2 1 0)");
+    }
+
+    #[test]
+    fn let_bindings_do_not_leak() {
+        let error = eval("(let ((hidden 1)) hidden) hidden").unwrap_err();
+        assert!(error.to_string().starts_with("unbound variable 'hidden'"), "{}", error);
+    }
+
+    #[test]
+    fn derived_conditionals() {
+        assert_eq!(eval("(cond ((+ 1 1) => (lambda (x) (* x 10))) (else 'none))").unwrap(), "20");
+        assert_eq!(eval("(cond (#f 1) (else 2))").unwrap(), "2");
+        assert_eq!(eval("(case (* 2 3) ((2 3 5 7) 'prime) ((1 4 6 8 9) 'composite) (else 'other))").unwrap(), "composite");
+        assert_eq!(eval("(list (when #t 'a 'b) (unless #f 'c))").unwrap(), "(b c)");
+        assert_eq!(eval("(list (and 1 2 3) (and 1 #f 3) (and) (or #f 2) (or) (not 0))").unwrap(), "(3 #f #t 2 #f #f)");
+    }
+
+    #[test]
+    fn and_and_or_short_circuit() {
+        assert_eq!(eval("(define n 0) (or #t (set! n 1)) (and #f (set! n 2)) n").unwrap(), "0");
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt::Write;
+
+use crate::compiler::expect_args;
+use crate::error::Result;
+use crate::lowering::{check_builtin, Backend, Place, Scopes};
+use crate::native::RUNTIME;
+use crate::parser::{Argument, Expression};
+use crate::span::Span;
+use crate::value::Value;
+
+/// Translates a program to one self-contained C99 file: the native backend's
+/// runtime followed by a `scheme_main` that calls into it. Each expression
+/// yields a constant or a fresh temporary, which keeps evaluation order
+/// explicit and leaves the optimizing to the C compiler.
+struct CGen {
+    body: String,
+    indent: usize,
+    declarations: String,
+    /// Statements creating the string literals, run first in `scheme_main`.
+    strings: String,
+    scopes: Scopes,
+    temps: usize,
+    literals: usize,
+}
+
+/// Renders `bytes` as the contents of a C string literal.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' | b'?' => write!(escaped, "\\{}", byte as char).unwrap(),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => write!(escaped, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    escaped
+}
+
+impl CGen {
+    fn new() -> Self {
+        Self {
+            body: String::new(),
+            indent: 1,
+            declarations: String::new(),
+            strings: String::new(),
+            scopes: Scopes::default(),
+            temps: 0,
+            literals: 0,
+        }
+    }
+
+    fn line(&mut self, statement: &str) {
+        for _ in 0..self.indent {
+            self.body.push_str("    ");
+        }
+        self.body.push_str(statement);
+        self.body.push('\n');
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("t{}", self.temps - 1)
+    }
+
+    /// Stores `value` (a C expression) in a fresh temporary.
+    fn assign(&mut self, value: &str) -> String {
+        let temp = self.temp();
+        self.line(&format!("{} = {};", temp, value));
+        temp
+    }
+
+    fn locate(&mut self, span: &Span) {
+        let location = escape(span.to_string().as_bytes());
+        self.line(&format!("scm_location = \"{}\";", location));
+    }
+
+    fn variable(place: Place) -> String {
+        match place {
+            Place::Local(slot) => format!("local_{}", slot),
+            Place::Global(index) => format!("global_{}", index),
+        }
+    }
+
+    fn check_bound(&mut self, global: &str, name: &str, span: &Span) {
+        self.line(&format!("if ({} == UNBOUND) {{", global));
+        self.indent += 1;
+        self.locate(span);
+        self.line(&format!("scm_unbound(\"{}\");", escape(name.as_bytes())));
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn finish(self, source: &str) -> String {
+        let mut declarations = String::new();
+        if self.temps > 0 {
+            let temps = (0..self.temps).map(|i| format!("t{}", i)).collect::<Vec<String>>();
+            writeln!(declarations, "    value {};", temps.join(", ")).unwrap();
+        }
+        if self.scopes.frame_size > 0 {
+            let locals = (0..self.scopes.frame_size)
+                .map(|i| format!("local_{}", i))
+                .collect::<Vec<String>>();
+            writeln!(declarations, "    value {};", locals.join(", ")).unwrap();
+        }
+        format!(
+            "/* generated by scheme-compilator from {} */\n\n{}\n/* program */\n\n{}\nvoid scheme_main(void) {{\n{}{}{}}}\n",
+            escape(source.as_bytes()).replace("*/", "*\\/"),
+            RUNTIME,
+            self.declarations,
+            declarations,
+            self.strings,
+            self.body,
+        )
+    }
+}
+
+impl Backend for CGen {
+    type Value = String;
+
+    fn scopes(&mut self) -> &mut Scopes {
+        &mut self.scopes
+    }
+
+    fn allocate_global(&mut self, index: usize, name: &str) {
+        writeln!(
+            self.declarations,
+            "static value global_{} = UNBOUND; /* {} */",
+            index,
+            escape(name.as_bytes()).replace("*/", "*\\/")
+        )
+        .unwrap();
+    }
+
+    fn initialize_local(&mut self, slot: usize) {
+        self.line(&format!("local_{} = UNSPECIFIED;", slot));
+    }
+
+    fn unspecified(&mut self) -> String {
+        "UNSPECIFIED".to_string()
+    }
+
+    /// Keeps `-Wall` quiet about an unused temporary.
+    fn discard(&mut self, value: String) {
+        if value.starts_with('t') {
+            self.line(&format!("(void){};", value));
+        }
+    }
+
+    fn constant(&mut self, value: &Value) -> String {
+        match value {
+            Value::Int(n) => format!("FIXNUM(INT64_C({}))", n),
+            Value::Bool(true) => "TRUE".to_string(),
+            Value::Bool(false) => "FALSE".to_string(),
+            Value::String(string) => {
+                let name = format!("string_{}", self.literals);
+                self.literals += 1;
+                writeln!(self.declarations, "static value {};", name).unwrap();
+                writeln!(
+                    self.strings,
+                    "    {} = make_string(\"{}\", {});",
+                    name,
+                    escape(string.as_bytes()),
+                    string.len()
+                )
+                .unwrap();
+                name
+            }
+            _ => unreachable!("rejected by check_literal"),
+        }
+    }
+
+    fn load(&mut self, place: Place, name: &str, span: &Span) -> String {
+        let variable = Self::variable(place);
+        if let Place::Global(_) = place {
+            self.check_bound(&variable, name, span);
+        }
+        self.assign(&variable)
+    }
+
+    fn store(&mut self, place: Place, value: String) {
+        self.line(&format!("{} = {};", Self::variable(place), value));
+    }
+
+    fn update(&mut self, place: Place, value: String, name: &str, span: &Span) {
+        if let Place::Global(_) = place {
+            self.check_bound(&Self::variable(place), name, span);
+        }
+        self.store(place, value);
+    }
+
+    fn while_statement(&mut self, expr: &Expression) -> Result<String> {
+        let args = &expr.arguments;
+        expect_args("while", args, 1, None)?;
+        self.line("for (;;) {");
+        self.indent += 1;
+        let condition = self.expression(&args[0])?;
+        self.line(&format!("if ({} == FALSE) break;", condition));
+        for arg in &args[1..] {
+            let value = self.expression(arg)?;
+            self.discard(value);
+        }
+        self.indent -= 1;
+        self.line("}");
+        Ok("UNSPECIFIED".to_string())
+    }
+
+    fn if_statement(&mut self, expr: &Expression) -> Result<String> {
+        let args = &expr.arguments;
+        expect_args("if", args, 2, Some(3))?;
+        let condition = self.expression(&args[0])?;
+        let result = self.temp();
+        self.line(&format!("if ({} != FALSE) {{", condition));
+        self.indent += 1;
+        let value = self.expression(&args[1])?;
+        self.line(&format!("{} = {};", result, value));
+        self.indent -= 1;
+        self.line("} else {");
+        self.indent += 1;
+        let value = match args.get(2) {
+            Some(arg) => self.expression(arg)?,
+            None => "UNSPECIFIED".to_string(),
+        };
+        self.line(&format!("{} = {};", result, value));
+        self.indent -= 1;
+        self.line("}");
+        Ok(result)
+    }
+
+    fn builtin_call(&mut self, name: &str, expr: &Expression) -> Result<String> {
+        let function = check_builtin(name, expr)?;
+        let args = expr
+            .arguments
+            .iter()
+            .map(|arg| self.expression(arg))
+            .collect::<Result<Vec<String>>>()?;
+
+        self.locate(&expr.span);
+        let result = match name {
+            "display" if args.is_empty() => self.assign("scm_display(0, NULL)"),
+            "display" => {
+                let reversed = args.iter().rev().cloned().collect::<Vec<String>>();
+                let result = self.temp();
+                self.line("{");
+                self.indent += 1;
+                self.line(&format!("value args[] = {{{}}};", reversed.join(", ")));
+                self.line(&format!("{} = scm_display({}, args);", result, args.len()));
+                self.indent -= 1;
+                self.line("}");
+                result
+            }
+            "newline" | "read" | "read-line" => self.assign(&format!("{}()", function)),
+            // Binary operators fold left; one operand is combined with the identity.
+            _ => match args.split_first() {
+                None => match name {
+                    "*" => "FIXNUM(1)".to_string(),
+                    _ => "FIXNUM(0)".to_string(),
+                },
+                Some((first, [])) => {
+                    let identity = if name == "*" || name == "/" { "FIXNUM(1)" } else { "FIXNUM(0)" };
+                    self.assign(&format!("{}({}, {})", function, identity, first))
+                }
+                Some((first, rest)) => {
+                    let mut result = first.clone();
+                    for arg in rest {
+                        result = self.assign(&format!("{}({}, {})", function, result, arg));
+                    }
+                    result
+                }
+            },
+        };
+        Ok(result)
+    }
+}
+
+/// Translates a whole program to a C99 translation unit.
+pub fn generate(program: &[Argument], source: &str) -> Result<String> {
+    let mut cgen = CGen::new();
+    for arg in program {
+        cgen.toplevel(arg)?;
+    }
+    Ok(cgen.finish(source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn programs_translate_to_c_that_builds_and_runs() {
+        let source = "(define total 0)
+                      (define i 1)
+                      (while (<= i 10)
+                        (begin
+                          (define square (* i i))
+                          (set! total (+ total square)))
+                        (set! i (+ i 1)))
+                      (display \"sum of squares: \" total \"\\n\")
+                      (display (if (= (% total 5) 0) \"divisible\" \"not divisible\") \"\\n\")";
+        let program = parser::parse_source(source, "<test>").unwrap();
+        let c = generate(&program, "<test>").unwrap();
+        let dir = std::env::temp_dir().join(format!("scheme-emit-c-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (file, executable) = (dir.join("program.c"), dir.join("program"));
+        fs::write(&file, c).unwrap();
+        let status = Command::new("cc").args(["-std=c99", "-Wall", "-Werror", "-o"]).arg(&executable).arg(&file).status();
+        let output = Command::new(&executable).output();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(status.unwrap().success());
+        assert_eq!(String::from_utf8_lossy(&output.unwrap().stdout), "sum of squares: 385\ndivisible\n");
+    }
+}
@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::value::Value;
+
+/// One frame of local variables. The compiler resolves every local to a
+/// `(depth, slot)` pair, so lookups walk `depth` parents and index directly.
+/// Closures keep their defining frame alive.
+#[derive(Debug)]
+pub struct Environment {
+    slots: RefCell<Vec<Value>>,
+    parent: Option<Rc<Environment>>,
+}
+
+impl Environment {
+    pub fn new(size: usize, parent: Option<Rc<Environment>>) -> Rc<Self> {
+        Self::with_slots(vec![Value::Unspecified; size], parent)
+    }
+
+    pub fn with_slots(slots: Vec<Value>, parent: Option<Rc<Environment>>) -> Rc<Self> {
+        Rc::new(Self {
+            slots: RefCell::new(slots),
+            parent,
+        })
+    }
+
+    pub fn parent(&self) -> Option<&Rc<Environment>> {
+        self.parent.as_ref()
+    }
+
+    fn ancestor(&self, depth: usize) -> &Environment {
+        let mut env = self;
+        for _ in 0..depth {
+            env = env.parent.as_ref().expect("local resolved past the outermost frame");
+        }
+        env
+    }
+
+    pub fn get(&self, depth: usize, slot: usize) -> Value {
+        self.ancestor(depth).slots.borrow()[slot].clone()
+    }
+
+    pub fn set(&self, depth: usize, slot: usize, value: Value) {
+        self.ancestor(depth).slots.borrow_mut()[slot] = value;
+    }
+}
+
+/// Top-level bindings, addressed by the index the compiler assigned to each
+/// name. A slot is `None` until the name is defined.
+#[derive(Debug, Default)]
+pub struct Globals {
+    indices: HashMap<String, usize>,
+    names: Vec<String>,
+    values: Vec<Option<Value>>,
+}
+
+impl Globals {
+    /// The index of `name`, reserving an unbound slot on first use.
+    pub fn index(&mut self, name: &str) -> usize {
+        if let Some(index) = self.indices.get(name) {
+            return *index;
+        }
+        let index = self.names.len();
+        self.indices.insert(name.to_string(), index);
+        self.names.push(name.to_string());
+        self.values.push(None);
+        index
+    }
+
+    pub fn name(&self, index: usize) -> &str {
+        &self.names[index]
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.values[index].as_ref()
+    }
+
+    pub fn define(&mut self, name: &str, value: Value) {
+        let index = self.index(name);
+        self.values[index] = Some(value);
+    }
+
+    /// Rebinds an existing global; returns `false` when it is unbound.
+    pub fn set(&mut self, index: usize, value: Value) -> bool {
+        match &mut self.values[index] {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn define_at(&mut self, index: usize, value: Value) {
+        self.values[index] = Some(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::eval;
+
+    #[test]
+    fn top_level_definitions_persist_across_forms() {
+        assert_eq!(eval("(define x 1) (define (get) x) (set! x 2) (get)").unwrap(), "2");
+    }
+
+    #[test]
+    fn define_binds_in_the_current_frame() {
+        let source = "(define x 'outer)
+                      (define (f) (define x 'inner) x)
+                      (list (f) x)";
+        assert_eq!(eval(source).unwrap(), "(inner outer)");
+    }
+
+    #[test]
+    fn set_mutates_the_nearest_binding() {
+        let source = "(define x 1)
+                      (define (f x) (set! x 10) x)
+                      (list (f 5) x)";
+        assert_eq!(eval(source).unwrap(), "(10 1)");
+    }
+
+    #[test]
+    fn set_of_an_unbound_name_is_an_error() {
+        let error = eval("(set! nowhere 1)").unwrap_err();
+        assert!(error.to_string().starts_with("unbound variable 'nowhere'"), "{}", error);
+    }
+}
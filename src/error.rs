@@ -0,0 +1,173 @@
+use std::fmt;
+use std::io;
+
+use crate::span::Span;
+use crate::value::{Value, Written};
+
+#[derive(Debug)]
+pub enum Error {
+    Lex(String),
+    Parse(String),
+    /// The input ended in the middle of a datum, so more text may complete it.
+    Incomplete(String),
+    UnboundVariable(String),
+    Type {
+        procedure: String,
+        expected: String,
+        found: String,
+    },
+    Arity {
+        procedure: String,
+        expected: String,
+        found: usize,
+    },
+    DivisionByZero(String),
+    /// An index outside `0..length`, e.g. `(vector-ref v 10)` on a short vector.
+    OutOfRange {
+        procedure: String,
+        index: String,
+        length: usize,
+    },
+    /// A construct the native backend cannot compile.
+    Unsupported(String),
+    /// A command line the program cannot make sense of.
+    Usage(String),
+    Io(io::Error),
+    /// An object raised with no exception handler to catch it.
+    Raised(Value),
+    /// Any of the above, tied to the source location it was raised at.
+    At {
+        error: Box<Error>,
+        span: Span,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn type_error(procedure: &str, expected: &str, found: &str) -> Self {
+        Error::Type {
+            procedure: procedure.to_string(),
+            expected: expected.to_string(),
+            found: found.to_string(),
+        }
+    }
+
+    /// Attaches `span` unless the error already points somewhere more precise.
+    pub fn at(self, span: &Span) -> Self {
+        match self {
+            Error::At { .. } => self,
+            error => Error::At {
+                error: Box::new(error),
+                span: span.clone(),
+            },
+        }
+    }
+
+    /// Whether Scheme exception handlers get to see this error. `Raised` is
+    /// what remains of an exception once none of them caught it.
+    pub fn is_catchable(&self) -> bool {
+        match self {
+            Error::Raised(_) => false,
+            Error::At { error, .. } => error.is_catchable(),
+            _ => true,
+        }
+    }
+
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            Error::Incomplete(_) => true,
+            Error::At { error, .. } => error.is_incomplete(),
+            _ => false,
+        }
+    }
+
+    /// The full report shown to the user, with a source excerpt when known.
+    pub fn diagnostic(&self) -> String {
+        match self {
+            Error::At { span, .. } => format!("error: {}\n{}", self, span.snippet()),
+            _ => format!("error: {}", self),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lex(message) => write!(f, "{}", message),
+            Error::Parse(message) => write!(f, "{}", message),
+            Error::Incomplete(message) => write!(f, "{}", message),
+            Error::UnboundVariable(name) => write!(f, "unbound variable '{}'", name),
+            Error::Type {
+                procedure,
+                expected,
+                found,
+            } => write!(f, "{}: expected {}, got {}", procedure, expected, found),
+            Error::Arity {
+                procedure,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}: expected {} arguments, got {}",
+                procedure, expected, found
+            ),
+            Error::DivisionByZero(procedure) => write!(f, "{}: division by zero", procedure),
+            Error::OutOfRange {
+                procedure,
+                index,
+                length,
+            } => write!(
+                f,
+                "{}: index {} is out of range for length {}",
+                procedure, index, length
+            ),
+            Error::Unsupported(feature) => write!(f, "compiled programs do not support {}", feature),
+            Error::Usage(message) => write!(f, "{}", message),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Raised(Value::ErrorObject(error)) => write!(f, "{}", error),
+            Error::Raised(value) => write!(f, "uncaught exception: {}", Written(value)),
+            Error::At { error, span } => write!(f, "{} at {}", error, span),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::eval;
+
+    /// The error `source` fails with, without the location wrapped around it.
+    fn failure(source: &str) -> Error {
+        match eval(source).unwrap_err() {
+            Error::At { error, .. } => *error,
+            error => error,
+        }
+    }
+
+    #[test]
+    fn failures_are_reported_as_structured_errors() {
+        assert!(matches!(failure("#\\nonsense"), Error::Lex(_)));
+        assert!(matches!(failure(")"), Error::Parse(_)));
+        assert!(matches!(failure("(+ 1"), Error::Incomplete(_)));
+        assert!(matches!(failure("missing"), Error::UnboundVariable(name) if name == "missing"));
+        assert!(matches!(failure("(- \"a\")"), Error::Type { .. }));
+        assert!(matches!(failure("(car 1 2)"), Error::Arity { found: 2, .. }));
+        assert!(matches!(failure("(/ 1 0)"), Error::DivisionByZero(_)));
+    }
+
+    #[test]
+    fn errors_read_as_messages() {
+        let error = failure("(car 1 2)");
+        assert_eq!(error.to_string(), "car: expected 1 arguments, got 2");
+        assert_eq!(error.diagnostic(), "error: car: expected 1 arguments, got 2");
+    }
+}
@@ -0,0 +1,1324 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::builtins;
+use crate::compiler::{bad_syntax, define_target, symbol_name, Compiler};
+use crate::error::{Error, Result};
+use crate::parser::{Argument, Expression, LiteralVariable};
+use crate::span::Span;
+use crate::value::Value;
+use crate::vm::Vm;
+
+/// Separates an identifier from the number of the macro expansion that
+/// introduced it: `tmp\u{1}3`.
+const ALIAS: char = '\u{1}';
+/// Separates a local variable's name from the number that makes it unique.
+const LOCAL: char = '\u{2}';
+/// Ends the identifier a `gensym` stands for in code, `g@1\u{3}`, which keeps
+/// it apart from the symbol `g@1`.
+const GENSYM: char = '\u{3}';
+
+/// The forms the compiler implements. Macros expand into these.
+const SPECIAL_FORMS: &[&str] = &[
+    "begin",
+    "quote",
+    "define",
+    "set!",
+    "lambda",
+    "let",
+    "let*",
+    "letrec",
+    "letrec*",
+    "if",
+    "while",
+    "cond",
+    "case",
+    "when",
+    "unless",
+    "and",
+    "or",
+    "define-syntax",
+    "let-syntax",
+    "letrec-syntax",
+    "syntax-rules",
+    "define-macro",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+];
+
+/// The identifier as it was written, without the renaming done by expansion.
+pub fn base_name(name: &str) -> &str {
+    name.split([ALIAS, LOCAL, GENSYM]).next().unwrap()
+}
+
+/// The symbol identifier `id` was written as, without the renaming done by
+/// expansion: a gensym if it stands for one.
+fn datum(id: &str) -> Value {
+    let name = id.split([ALIAS, LOCAL]).next().unwrap();
+    match name.strip_suffix(GENSYM) {
+        Some(name) => Value::Gensym(Rc::from(name)),
+        None => Value::symbol(name),
+    }
+}
+
+/// `arg` with the gensyms in it turned into identifiers.
+fn identifiers(arg: Argument) -> Argument {
+    match arg {
+        Argument::Expression(expr) => Argument::Expression(Expression {
+            function: Box::new(identifiers(*expr.function)),
+            arguments: expr.arguments.into_iter().map(identifiers).collect(),
+            rest: expr.rest.map(|rest| Box::new(identifiers(*rest))),
+            span: expr.span,
+        }),
+        Argument::LiteralVariable(LiteralVariable {
+            value: Value::Gensym(name),
+            span,
+        }) => symbol(&format!("{}{}", name, GENSYM), &span),
+        arg => arg,
+    }
+}
+
+/// What an identifier refers to.
+#[derive(Clone)]
+enum Meaning {
+    /// A variable or special form, by the name the compiler will see.
+    Variable(Rc<str>),
+    Macro(Rc<Macro>),
+    /// A `define-macro` keyword and its transformer procedure.
+    Procedure(Rc<str>, Value),
+}
+
+/// One level of lexical scope: identifiers as written, mapped to their meaning.
+/// The root scope holds the top-level macros; global variables are implicit.
+#[derive(Default)]
+struct Scope {
+    bindings: RefCell<HashMap<Rc<str>, Meaning>>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    fn child(parent: &Rc<Scope>) -> Rc<Scope> {
+        Rc::new(Scope {
+            bindings: RefCell::default(),
+            parent: Some(parent.clone()),
+        })
+    }
+
+    fn is_toplevel(&self) -> bool {
+        self.parent.is_none()
+    }
+}
+
+/// A `syntax-rules` transformer and the scope it was defined in.
+struct Macro {
+    name: String,
+    ellipsis: String,
+    literals: Vec<Rc<str>>,
+    /// `(pattern, template)` pairs, tried in order.
+    rules: Vec<(Argument, Argument)>,
+    env: Rc<Scope>,
+}
+
+/// What a pattern variable matched: one form, or one match per repetition
+/// of the ellipsis it sits under.
+#[derive(Clone)]
+enum Match {
+    One(Argument),
+    Many(Vec<Match>),
+}
+
+type Bindings = HashMap<Rc<str>, Match>;
+
+/// What a top-level form turned into.
+pub enum Toplevel {
+    /// A `begin` whose forms are themselves top-level forms, in order.
+    Begin(Vec<Argument>),
+    /// A fully expanded form, ready to compile.
+    Form(Argument),
+    /// A syntax definition, which has nothing left to run.
+    Syntax,
+}
+
+/// Expands macro uses into the special forms the compiler knows.
+///
+/// Expansion is hygienic by renaming: an identifier a template introduces
+/// becomes an alias that is looked up where the macro was defined, and every
+/// local variable gets a unique name, so neither side can capture the other.
+pub struct Expander {
+    global: Rc<Scope>,
+    /// The identifier and definition scope behind each alias, by number.
+    aliases: Vec<(Rc<str>, Rc<Scope>)>,
+    locals: usize,
+}
+
+impl fmt::Debug for Expander {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<expander>")
+    }
+}
+
+fn symbol(name: &str, span: &Span) -> Argument {
+    Argument::LiteralVariable(LiteralVariable {
+        value: Value::symbol(name),
+        span: span.clone(),
+    })
+}
+
+/// The elements of a list form and its dotted tail, if any. `(a . (b c))` is
+/// the same list as `(a b c)`.
+fn items(arg: &Argument) -> Option<(Vec<&Argument>, Option<&Argument>)> {
+    let mut items = Vec::new();
+    let mut current = arg;
+    loop {
+        match current {
+            Argument::Expression(expr) => {
+                items.push(expr.function.as_ref());
+                items.extend(&expr.arguments);
+                match &expr.rest {
+                    Some(rest) => current = rest,
+                    None => return Some((items, None)),
+                }
+            }
+            Argument::LiteralVariable(literal) if matches!(literal.value, Value::Nil) => {
+                return Some((items, None))
+            }
+            _ if items.is_empty() => return None,
+            tail => return Some((items, Some(tail))),
+        }
+    }
+}
+
+/// The elements of a vector literal, as forms.
+fn vector_items(arg: &Argument) -> Option<Vec<Argument>> {
+    match arg {
+        Argument::LiteralVariable(LiteralVariable {
+            value: Value::Vector(elements),
+            span,
+        }) => Some(elements.borrow().iter().map(|element| Argument::from_datum(element, span)).collect()),
+        _ => None,
+    }
+}
+
+/// Builds the list form `(items... . tail)`.
+fn list(items: Vec<Argument>, tail: Option<Argument>, span: &Span) -> Argument {
+    let mut items = items;
+    let mut tail = tail;
+    if let Some(rest) = &tail {
+        if let Some((more, rest)) = items_owned(rest) {
+            items.extend(more);
+            tail = rest;
+        }
+    }
+    let mut items = items.into_iter();
+    match items.next() {
+        Some(function) => Argument::Expression(Expression {
+            function: Box::new(function),
+            arguments: items.collect(),
+            rest: tail.map(Box::new),
+            span: span.clone(),
+        }),
+        None => tail.unwrap_or_else(|| {
+            Argument::LiteralVariable(LiteralVariable {
+                value: Value::Nil,
+                span: span.clone(),
+            })
+        }),
+    }
+}
+
+fn items_owned(arg: &Argument) -> Option<(Vec<Argument>, Option<Argument>)> {
+    let (items, tail) = items(arg)?;
+    Some((items.into_iter().cloned().collect(), tail.cloned()))
+}
+
+/// `arg` as data, with the renaming done by expansion undone.
+fn strip(arg: &Argument) -> Argument {
+    match arg {
+        Argument::Expression(expr) => Argument::Expression(Expression {
+            function: Box::new(strip(&expr.function)),
+            arguments: expr.arguments.iter().map(strip).collect(),
+            rest: expr.rest.as_deref().map(|rest| Box::new(strip(rest))),
+            span: expr.span.clone(),
+        }),
+        Argument::LiteralVariable(literal) => match &literal.value {
+            Value::Symbol(id) => Argument::LiteralVariable(LiteralVariable {
+                value: datum(id),
+                span: literal.span.clone(),
+            }),
+            _ => arg.clone(),
+        },
+    }
+}
+
+/// `(quote datum)`, with the renaming undone.
+fn quoted(datum: &Argument) -> Argument {
+    list(vec![symbol("quote", datum.span()), strip(datum)], None, datum.span())
+}
+
+/// A call to the primitive procedure `name`. The procedure itself is the
+/// operator, so redefining the global `name` does not change the call.
+fn call(name: &str, args: Vec<Argument>, span: &Span) -> Argument {
+    let procedure = Argument::LiteralVariable(LiteralVariable {
+        value: builtins::primitive(name).unwrap(),
+        span: span.clone(),
+    });
+    list([vec![procedure], args].concat(), None, span)
+}
+
+/// Whether two self-evaluating pattern and input data are the same.
+fn same_datum(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a == b,
+        (a, b) => a.eqv(b),
+    }
+}
+
+impl Expander {
+    pub fn new() -> Self {
+        Self {
+            global: Rc::new(Scope::default()),
+            aliases: Vec::new(),
+            locals: 0,
+        }
+    }
+
+    /// Expands one top-level form. Top-level `begin` is left for the caller to
+    /// splice, so that each form inside it can use macros the ones before define.
+    /// `vm` runs the transformers of `define-macro`.
+    pub fn expand_toplevel(&mut self, arg: &Argument, vm: &mut Vm) -> Result<Toplevel> {
+        let scope = self.global.clone();
+        let mut expansion = Expansion { expander: self, vm };
+        let form = expansion.expand_head(arg, &scope)?;
+        match (&form, expansion.special_form(&form, &scope).as_deref()) {
+            (Argument::Expression(expr), Some("begin")) => Ok(Toplevel::Begin(expr.arguments.clone())),
+            (Argument::Expression(expr), Some("define-syntax")) => {
+                expansion.define_syntax(expr, &scope).map_err(|error| error.at(&expr.span))?;
+                Ok(Toplevel::Syntax)
+            }
+            (Argument::Expression(expr), Some("define-macro")) => {
+                expansion.define_macro(expr, &scope).map_err(|error| error.at(&expr.span))?;
+                Ok(Toplevel::Syntax)
+            }
+            _ => Ok(Toplevel::Form(expansion.expand(&form, &scope)?)),
+        }
+    }
+}
+
+/// One expansion, with the machine that runs procedural macros.
+struct Expansion<'a> {
+    expander: &'a mut Expander,
+    vm: &'a mut Vm,
+}
+
+impl Expansion<'_> {
+
+    fn resolve(&self, id: &str, scope: &Rc<Scope>) -> Meaning {
+        let mut current = Some(scope);
+        while let Some(scope) = current {
+            if let Some(meaning) = scope.bindings.borrow().get(id) {
+                return meaning.clone();
+            }
+            current = scope.parent.as_ref();
+        }
+        // A symbol a program builds may look like an alias, but name none.
+        let alias = id
+            .split_once(ALIAS)
+            .and_then(|(_, number)| self.expander.aliases.get(number.parse::<usize>().ok()?));
+        match alias {
+            Some((name, env)) => self.resolve(name, env),
+            None => Meaning::Variable(Rc::from(id)),
+        }
+    }
+
+    /// The special form `arg` is a use of, if any.
+    fn special_form(&self, arg: &Argument, scope: &Rc<Scope>) -> Option<Rc<str>> {
+        let Argument::Expression(expr) = arg else {
+            return None;
+        };
+        match self.resolve(symbol_name(&expr.function)?, scope) {
+            Meaning::Variable(name) if SPECIAL_FORMS.contains(&&*name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Whether identifier `arg` means the special form or variable `name`,
+    /// as `else` and `=>` do in `cond`.
+    fn is_keyword(&self, arg: &Argument, name: &str, scope: &Rc<Scope>) -> bool {
+        match symbol_name(arg).map(|id| self.resolve(id, scope)) {
+            Some(Meaning::Variable(meaning)) => &*meaning == name,
+            _ => false,
+        }
+    }
+
+    /// Gives the variable `id` a unique name in `scope`.
+    fn bind(&mut self, id: &str, scope: &Rc<Scope>) -> Rc<str> {
+        let name: Rc<str> = Rc::from(format!("{}{}{}", base_name(id), LOCAL, self.expander.locals));
+        self.expander.locals += 1;
+        scope.bindings.borrow_mut().insert(Rc::from(id), Meaning::Variable(name.clone()));
+        name
+    }
+
+    /// The name a `define` of `id` binds: a global at top level, otherwise a
+    /// local of the innermost scope (which a body may already have bound).
+    /// A global a macro introduces gets a unique name too, so that it cannot
+    /// overwrite one the program defines under the same name.
+    fn define_name(&mut self, id: &str, scope: &Rc<Scope>) -> Rc<str> {
+        if scope.is_toplevel() && !id.contains(ALIAS) {
+            scope.bindings.borrow_mut().remove(id);
+            return Rc::from(id);
+        }
+        if let Some(Meaning::Variable(name)) = scope.bindings.borrow().get(id) {
+            return name.clone();
+        }
+        self.bind(id, scope)
+    }
+
+    /// Expands macro uses in operator position until `arg` is something else.
+    fn expand_head(&mut self, arg: &Argument, scope: &Rc<Scope>) -> Result<Argument> {
+        let mut form = arg.clone();
+        loop {
+            let Argument::Expression(expr) = &form else {
+                return Ok(form);
+            };
+            form = match symbol_name(&expr.function).map(|id| self.resolve(id, scope)) {
+                Some(Meaning::Macro(transformer)) => self.transcribe(&transformer, expr, scope),
+                Some(Meaning::Procedure(name, transformer)) => self.call_transformer(&name, transformer, expr),
+                _ => return Ok(form),
+            }
+            .map_err(|error| error.at(&expr.span))?;
+        }
+    }
+
+    fn expand(&mut self, arg: &Argument, scope: &Rc<Scope>) -> Result<Argument> {
+        let form = self.expand_head(arg, scope)?;
+        match &form {
+            Argument::LiteralVariable(literal) => match &literal.value {
+                Value::Symbol(id) => match self.resolve(id, scope) {
+                    Meaning::Variable(name) => Ok(symbol(&name, &literal.span)),
+                    Meaning::Macro(transformer) => Err(bad_syntax(&transformer.name).at(&literal.span)),
+                    Meaning::Procedure(name, _) => Err(bad_syntax(&name).at(&literal.span)),
+                },
+                _ => Ok(form),
+            },
+            Argument::Expression(expr) => match self.special_form(&form, scope) {
+                Some(name) => self.expand_special(&name, expr, scope).map_err(|error| error.at(&expr.span)),
+                None => Ok(Argument::Expression(Expression {
+                    function: Box::new(self.expand(&expr.function, scope)?),
+                    arguments: self.expand_all(&expr.arguments, scope)?,
+                    rest: match &expr.rest {
+                        Some(rest) => Some(Box::new(self.expand(rest, scope)?)),
+                        None => None,
+                    },
+                    span: expr.span.clone(),
+                })),
+            },
+        }
+    }
+
+    fn expand_all(&mut self, args: &[Argument], scope: &Rc<Scope>) -> Result<Vec<Argument>> {
+        args.iter().map(|arg| self.expand(arg, scope)).collect()
+    }
+
+    /// Expands a body in `scope`. Its definitions are found first, so every
+    /// form in the body sees all of them. A `begin` among them is spliced in,
+    /// as the definitions a macro expands to must bind in the body itself.
+    fn body(&mut self, body: &[Argument], scope: &Rc<Scope>) -> Result<Vec<Argument>> {
+        let mut forms = Vec::new();
+        let mut pending: Vec<Argument> = body.iter().rev().cloned().collect();
+        while let Some(arg) = pending.pop() {
+            let form = self.expand_head(&arg, scope)?;
+            match (&form, self.special_form(&form, scope).as_deref()) {
+                (Argument::Expression(expr), Some("begin")) => {
+                    pending.extend(expr.arguments.iter().rev().cloned());
+                    continue;
+                }
+                (Argument::Expression(expr), Some("define-syntax")) => {
+                    self.define_syntax(expr, scope).map_err(|error| error.at(&expr.span))?;
+                    continue;
+                }
+                (Argument::Expression(expr), Some("define-macro")) => {
+                    self.define_macro(expr, scope).map_err(|error| error.at(&expr.span))?;
+                    continue;
+                }
+                (Argument::Expression(expr), Some("define")) => {
+                    if let Some(id) = define_target(&expr.arguments) {
+                        self.define_name(id, scope);
+                    }
+                }
+                _ => (),
+            }
+            forms.push(form);
+        }
+        forms.iter().map(|form| self.expand(form, scope)).collect()
+    }
+
+    fn expand_special(&mut self, name: &str, expr: &Expression, scope: &Rc<Scope>) -> Result<Argument> {
+        let args = &expr.arguments;
+        let span = &expr.span;
+        let form = |arguments: Vec<Argument>| list([vec![symbol(name, span)], arguments].concat(), None, span);
+        // Malformed forms are passed on as they are, for the compiler to report.
+        let unchanged = || Ok(Argument::Expression(expr.clone()));
+
+        match name {
+            "quote" => Ok(strip(&Argument::Expression(expr.clone()))),
+            "begin" => Ok(form(self.body(args, &Scope::child(scope))?)),
+            "define" => {
+                let Some(id) = define_target(args) else {
+                    return unchanged();
+                };
+                let name = self.define_name(id, scope);
+                match &args[0] {
+                    Argument::Expression(signature) => {
+                        let inner = Scope::child(scope);
+                        let params = list(
+                            signature.arguments.clone(),
+                            signature.rest.as_deref().cloned(),
+                            &signature.span,
+                        );
+                        let Some(params) = self.params(&params, &inner) else {
+                            return unchanged();
+                        };
+                        let signature = list(
+                            vec![symbol(&name, &signature.span)],
+                            Some(params),
+                            &signature.span,
+                        );
+                        Ok(form([vec![signature], self.body(&args[1..], &inner)?].concat()))
+                    }
+                    target => {
+                        let mut rest = vec![symbol(&name, target.span())];
+                        rest.extend(self.expand_all(&args[1..], scope)?);
+                        Ok(form(rest))
+                    }
+                }
+            }
+            "lambda" => {
+                let Some(first) = args.first() else {
+                    return unchanged();
+                };
+                let inner = Scope::child(scope);
+                let Some(params) = self.params(first, &inner) else {
+                    return unchanged();
+                };
+                Ok(form([vec![params], self.body(&args[1..], &inner)?].concat()))
+            }
+            "let" => match args.first().and_then(symbol_name) {
+                // (let name ((var init) ...) body...)
+                Some(id) => {
+                    let Some(bindings) = args.get(1).and_then(items) else {
+                        return unchanged();
+                    };
+                    let Some(inits) = self.binding_inits(&bindings.0, scope)? else {
+                        return unchanged();
+                    };
+                    let outer = Scope::child(scope);
+                    let loop_name = self.bind(id, &outer);
+                    let inner = Scope::child(&outer);
+                    let bindings = self.rebind(&bindings.0, inits, &inner, span);
+                    let body = self.body(&args[2..], &inner)?;
+                    Ok(form([vec![symbol(&loop_name, args[0].span()), bindings], body].concat()))
+                }
+                // (let ((var init) ...) body...)
+                None => {
+                    let Some(bindings) = args.first().and_then(items) else {
+                        return unchanged();
+                    };
+                    let Some(inits) = self.binding_inits(&bindings.0, scope)? else {
+                        return unchanged();
+                    };
+                    let inner = Scope::child(scope);
+                    let bindings = self.rebind(&bindings.0, inits, &inner, span);
+                    Ok(form([vec![bindings], self.body(&args[1..], &inner)?].concat()))
+                }
+            },
+            "let*" => {
+                let Some((bindings, None)) = args.first().and_then(items) else {
+                    return unchanged();
+                };
+                let mut inner = scope.clone();
+                let mut renamed = Vec::new();
+                for binding in bindings {
+                    let Some(inits) = self.binding_inits(&[binding], &inner)? else {
+                        return unchanged();
+                    };
+                    inner = Scope::child(&inner);
+                    if let Argument::Expression(pair) = self.rebind(&[binding], inits, &inner, span) {
+                        renamed.push(*pair.function);
+                    }
+                }
+                let inner = Scope::child(&inner);
+                let bindings = list(renamed, None, span);
+                Ok(form([vec![bindings], self.body(&args[1..], &inner)?].concat()))
+            }
+            "letrec" | "letrec*" => {
+                let Some((bindings, None)) = args.first().and_then(items) else {
+                    return unchanged();
+                };
+                let inner = Scope::child(scope);
+                for binding in &bindings {
+                    if let Some((pair, _)) = items(binding) {
+                        if let Some(id) = pair.first().and_then(|id| symbol_name(id)) {
+                            self.bind(id, &inner);
+                        }
+                    }
+                }
+                let Some(inits) = self.binding_inits(&bindings, &inner)? else {
+                    return unchanged();
+                };
+                let renamed = bindings
+                    .iter()
+                    .zip(inits)
+                    .map(|(binding, init)| {
+                        let id = symbol_name(items(binding).unwrap().0[0]).unwrap();
+                        let name = self.resolve(id, &inner);
+                        let Meaning::Variable(name) = name else { unreachable!() };
+                        list(vec![symbol(&name, binding.span()), init], None, binding.span())
+                    })
+                    .collect();
+                let bindings = list(renamed, None, span);
+                Ok(form([vec![bindings], self.body(&args[1..], &inner)?].concat()))
+            }
+            "set!" => match args.first().and_then(symbol_name) {
+                Some(id) => {
+                    let target = match self.resolve(id, scope) {
+                        Meaning::Variable(name) => symbol(&name, args[0].span()),
+                        Meaning::Macro(transformer) => return Err(bad_syntax(&transformer.name)),
+                        Meaning::Procedure(name, _) => return Err(bad_syntax(&name)),
+                    };
+                    Ok(form([vec![target], self.expand_all(&args[1..], scope)?].concat()))
+                }
+                None => unchanged(),
+            },
+            "cond" => {
+                let mut clauses = Vec::new();
+                for clause in args {
+                    let Some((parts, None)) = items(clause) else {
+                        return unchanged();
+                    };
+                    let mut expanded = Vec::new();
+                    for (i, part) in parts.iter().enumerate() {
+                        expanded.push(match i {
+                            0 if self.is_keyword(part, "else", scope) => symbol("else", part.span()),
+                            1 if self.is_keyword(part, "=>", scope) => symbol("=>", part.span()),
+                            _ => self.expand(part, scope)?,
+                        });
+                    }
+                    clauses.push(list(expanded, None, clause.span()));
+                }
+                Ok(form(clauses))
+            }
+            "case" => {
+                let Some(key) = args.first() else {
+                    return unchanged();
+                };
+                let mut expanded = vec![self.expand(key, scope)?];
+                for clause in &args[1..] {
+                    let Some((parts, None)) = items(clause) else {
+                        return unchanged();
+                    };
+                    let mut clause_parts = Vec::new();
+                    for (i, part) in parts.iter().enumerate() {
+                        clause_parts.push(match i {
+                            0 if self.is_keyword(part, "else", scope) => symbol("else", part.span()),
+                            0 => strip(part),
+                            1 if self.is_keyword(part, "=>", scope) => symbol("=>", part.span()),
+                            _ => self.expand(part, scope)?,
+                        });
+                    }
+                    expanded.push(list(clause_parts, None, clause.span()));
+                }
+                Ok(form(expanded))
+            }
+            "define-syntax" | "define-macro" => {
+                // Only reached outside a body, e.g. inside `if`.
+                if scope.is_toplevel() {
+                    return Err(bad_syntax(name));
+                }
+                match name {
+                    "define-syntax" => self.define_syntax(expr, scope)?,
+                    _ => self.define_macro(expr, scope)?,
+                }
+                Ok(list(vec![symbol("begin", span)], None, span))
+            }
+            "let-syntax" | "letrec-syntax" => {
+                let Some((bindings, None)) = args.first().and_then(items) else {
+                    return Err(bad_syntax(name));
+                };
+                let inner = Scope::child(scope);
+                let env = if name == "let-syntax" { scope } else { &inner };
+                for binding in bindings {
+                    let Some((parts, None)) = items(binding) else {
+                        return Err(bad_syntax(name));
+                    };
+                    let [keyword, spec] = parts[..] else {
+                        return Err(bad_syntax(name));
+                    };
+                    let id = symbol_name(keyword).ok_or_else(|| bad_syntax(name))?;
+                    let transformer = self.syntax_rules(id, spec, env)?;
+                    inner.bindings.borrow_mut().insert(Rc::from(id), Meaning::Macro(Rc::new(transformer)));
+                }
+                let body = self.body(&args[1..], &inner)?;
+                let empty = list(Vec::new(), None, span);
+                Ok(list([vec![symbol("let", span), empty], body].concat(), None, span))
+            }
+            "syntax-rules" => Err(bad_syntax("syntax-rules")),
+            "quasiquote" => match args.as_slice() {
+                [template] => self.quasiquote(template, 1, scope),
+                _ => Err(bad_syntax("quasiquote")),
+            },
+            "unquote" | "unquote-splicing" => Err(Error::Parse(format!("{}: not in quasiquote", name))),
+            // if, while, when, unless, and, or
+            _ => Ok(form(self.expand_all(args, scope)?)),
+        }
+    }
+
+    /// The datum of `arg` if it is `(name datum)`, as `,x` is `(unquote x)`.
+    fn abbreviation<'b>(&self, arg: &'b Argument, name: &str, scope: &Rc<Scope>) -> Option<&'b Argument> {
+        match arg {
+            Argument::Expression(expr)
+                if expr.arguments.len() == 1 && expr.rest.is_none() && self.is_keyword(&expr.function, name, scope) =>
+            {
+                Some(&expr.arguments[0])
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `template`, inside `depth` levels of quasiquote, has an unquote
+    /// that escapes all of them.
+    fn unquotes(&self, template: &Argument, depth: usize, scope: &Rc<Scope>) -> bool {
+        let unquoted = self
+            .abbreviation(template, "unquote", scope)
+            .or_else(|| self.abbreviation(template, "unquote-splicing", scope));
+        if let Some(datum) = unquoted {
+            return depth == 1 || self.unquotes(datum, depth - 1, scope);
+        }
+        if let Some(datum) = self.abbreviation(template, "quasiquote", scope) {
+            return self.unquotes(datum, depth + 1, scope);
+        }
+        match template {
+            Argument::Expression(expr) => std::iter::once(expr.function.as_ref())
+                .chain(&expr.arguments)
+                .chain(expr.rest.as_deref())
+                .any(|part| self.unquotes(part, depth, scope)),
+            Argument::LiteralVariable(literal) => match &literal.value {
+                Value::Vector(elements) => elements
+                    .borrow()
+                    .iter()
+                    .any(|element| self.unquotes(&Argument::from_datum(element, &literal.span), depth, scope)),
+                _ => false,
+            },
+        }
+    }
+
+    /// Expands the `template` of a quasiquote nested `depth` levels deep into
+    /// code that builds it. Parts without unquotes are simply quoted.
+    fn quasiquote(&mut self, template: &Argument, depth: usize, scope: &Rc<Scope>) -> Result<Argument> {
+        if !self.unquotes(template, depth, scope) {
+            return Ok(quoted(template));
+        }
+        let span = template.span();
+        if let (Some(datum), 1) = (self.abbreviation(template, "unquote", scope), depth) {
+            return self.expand(datum, scope);
+        }
+        if let (Some(_), 1) = (self.abbreviation(template, "unquote-splicing", scope), depth) {
+            return Err(Error::Parse("unquote-splicing: not in a list".to_string()));
+        }
+        // Nested unquotes and quasiquotes are kept as lists, one level shallower or deeper.
+        let depth = match template {
+            _ if self.abbreviation(template, "unquote", scope).is_some() => depth - 1,
+            _ if self.abbreviation(template, "unquote-splicing", scope).is_some() => depth - 1,
+            _ if self.abbreviation(template, "quasiquote", scope).is_some() => depth + 1,
+            _ => depth,
+        };
+        match template {
+            Argument::Expression(expr) => {
+                let elements: Vec<&Argument> = std::iter::once(expr.function.as_ref()).chain(&expr.arguments).collect();
+                self.quasiquote_list(&elements, expr.rest.as_deref(), depth, scope, span)
+            }
+            Argument::LiteralVariable(literal) => {
+                let Value::Vector(elements) = &literal.value else {
+                    return Ok(quoted(template));
+                };
+                let elements: Vec<Argument> =
+                    elements.borrow().iter().map(|element| Argument::from_datum(element, span)).collect();
+                let elements = self.quasiquote_list(&elements.iter().collect::<Vec<_>>(), None, depth, scope, span)?;
+                Ok(call("list->vector", vec![elements], span))
+            }
+        }
+    }
+
+    /// Code that builds the list of `elements` followed by `tail`, splicing in
+    /// the lists that `,@` elements evaluate to.
+    fn quasiquote_list(
+        &mut self,
+        elements: &[&Argument],
+        tail: Option<&Argument>,
+        depth: usize,
+        scope: &Rc<Scope>,
+        span: &Span,
+    ) -> Result<Argument> {
+        let mut result = match tail {
+            Some(tail) => self.quasiquote(tail, depth, scope)?,
+            None => quoted(&list(Vec::new(), None, span)),
+        };
+        for element in elements.iter().rev() {
+            result = match self.abbreviation(element, "unquote-splicing", scope) {
+                Some(spliced) if depth == 1 => call("append", vec![self.expand(spliced, scope)?, result], span),
+                _ => call("cons", vec![self.quasiquote(element, depth, scope)?, result], span),
+            };
+        }
+        Ok(result)
+    }
+
+    /// Binds the parameters in `params` (`(a b . rest)`, `args` or `()`) in
+    /// `scope`, returning them renamed.
+    fn params(&mut self, params: &Argument, scope: &Rc<Scope>) -> Option<Argument> {
+        let (ids, rest) = match symbol_name(params) {
+            Some(_) => (Vec::new(), Some(params)),
+            None => items(params)?,
+        };
+        let mut renamed = Vec::new();
+        for id in ids {
+            renamed.push(symbol(&self.bind(symbol_name(id)?, scope), id.span()));
+        }
+        let rest = match rest {
+            Some(rest) => Some(symbol(&self.bind(symbol_name(rest)?, scope), rest.span())),
+            None => None,
+        };
+        Some(list(renamed, rest, params.span()))
+    }
+
+    /// Expands the init of each `(var init)` binding in `scope`; `None` if a
+    /// binding is malformed.
+    fn binding_inits(&mut self, bindings: &[&Argument], scope: &Rc<Scope>) -> Result<Option<Vec<Argument>>> {
+        let mut inits = Vec::new();
+        for binding in bindings {
+            match items(binding) {
+                Some((pair, None)) if pair.len() == 2 && symbol_name(pair[0]).is_some() => {
+                    inits.push(self.expand(pair[1], scope)?)
+                }
+                _ => return Ok(None),
+            }
+        }
+        Ok(Some(inits))
+    }
+
+    /// Binds the variables of `bindings` in `scope` and pairs their new names
+    /// with the already expanded `inits`.
+    fn rebind(&mut self, bindings: &[&Argument], inits: Vec<Argument>, scope: &Rc<Scope>, span: &Span) -> Argument {
+        let renamed = bindings
+            .iter()
+            .zip(inits)
+            .map(|(binding, init)| {
+                let variable = items(binding).unwrap().0[0];
+                let name = self.bind(symbol_name(variable).unwrap(), scope);
+                list(vec![symbol(&name, variable.span()), init], None, binding.span())
+            })
+            .collect();
+        list(renamed, None, span)
+    }
+
+    /// `(define-syntax keyword (syntax-rules ...))`
+    fn define_syntax(&mut self, expr: &Expression, scope: &Rc<Scope>) -> Result<()> {
+        let [keyword, spec] = expr.arguments.as_slice() else {
+            return Err(bad_syntax("define-syntax"));
+        };
+        let id = symbol_name(keyword).ok_or_else(|| bad_syntax("define-syntax"))?;
+        let transformer = self.syntax_rules(id, spec, scope)?;
+        Self::bind_keyword(id, Meaning::Macro(Rc::new(transformer)), scope);
+        Ok(())
+    }
+
+    fn bind_keyword(id: &str, meaning: Meaning, scope: &Rc<Scope>) {
+        let id = match scope.is_toplevel() {
+            true => base_name(id),
+            false => id,
+        };
+        scope.bindings.borrow_mut().insert(Rc::from(id), meaning);
+    }
+
+    /// `(define-macro (keyword . params) body...)` or `(define-macro keyword
+    /// transformer)`. The transformer is evaluated at top level, even when the
+    /// keyword is bound in a body.
+    fn define_macro(&mut self, expr: &Expression, scope: &Rc<Scope>) -> Result<()> {
+        let (keyword, transformer) = match expr.arguments.as_slice() {
+            [Argument::Expression(signature), body @ ..] if !body.is_empty() => {
+                let params = list(
+                    signature.arguments.clone(),
+                    signature.rest.as_deref().cloned(),
+                    &signature.span,
+                );
+                let lambda = [vec![symbol("lambda", &expr.span), params], body.to_vec()].concat();
+                (signature.function.as_ref(), list(lambda, None, &expr.span))
+            }
+            [keyword, transformer] => (keyword, transformer.clone()),
+            _ => return Err(bad_syntax("define-macro")),
+        };
+        let id = symbol_name(keyword).ok_or_else(|| bad_syntax("define-macro"))?;
+
+        let global = self.expander.global.clone();
+        let transformer = self.expand(&transformer, &global)?;
+        let code = Compiler::compile(&mut self.vm.globals, &transformer)?;
+        let procedure = self.vm.execute(code)?;
+        if !matches!(procedure, Value::Procedure(_)) {
+            return Err(Error::type_error("define-macro", "a procedure", procedure.type_name()));
+        }
+        Self::bind_keyword(id, Meaning::Procedure(Rc::from(base_name(id)), procedure), scope);
+        Ok(())
+    }
+
+    /// Rewrites a use of a `define-macro` keyword by calling its transformer
+    /// with the operands as data, and reading the result back as a form.
+    fn call_transformer(&mut self, name: &str, transformer: Value, expr: &Expression) -> Result<Argument> {
+        if expr.rest.is_some() {
+            return Err(bad_syntax(name));
+        }
+        let operands = expr.arguments.iter().map(|arg| strip(arg).to_datum()).collect();
+        let expansion = self.vm.apply(transformer, operands, &expr.span)?;
+        Ok(identifiers(Argument::from_datum(&expansion, &expr.span)))
+    }
+
+    /// Parses `(syntax-rules [ellipsis] (literal ...) (pattern template) ...)`.
+    fn syntax_rules(&self, keyword: &str, spec: &Argument, env: &Rc<Scope>) -> Result<Macro> {
+        let error = || bad_syntax("syntax-rules");
+        let (parts, None) = items(spec).ok_or_else(error)? else {
+            return Err(error());
+        };
+        if !self.is_keyword(parts[0], "syntax-rules", env) {
+            return Err(Error::Parse(format!("define-syntax: expected syntax-rules for '{}'", base_name(keyword))));
+        }
+        let (ellipsis, rest) = match parts.get(1).and_then(|arg| symbol_name(arg)) {
+            Some(ellipsis) => (base_name(ellipsis).to_string(), &parts[2..]),
+            None => ("...".to_string(), &parts[1..]),
+        };
+        let (literals, rules) = rest.split_first().ok_or_else(error)?;
+        let literals = match items(literals) {
+            Some((literals, None)) => literals
+                .into_iter()
+                .map(|literal| symbol_name(literal).map(Rc::from).ok_or_else(error))
+                .collect::<Result<Vec<Rc<str>>>>()?,
+            _ => return Err(error()),
+        };
+        let rules = rules
+            .iter()
+            .map(|rule| match items(rule) {
+                Some((rule, None)) if rule.len() == 2 && matches!(rule[0], Argument::Expression(_)) => {
+                    Ok((rule[0].clone(), rule[1].clone()))
+                }
+                _ => Err(error()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Macro {
+            name: base_name(keyword).to_string(),
+            ellipsis,
+            literals,
+            rules,
+            env: env.clone(),
+        })
+    }
+
+    /// Rewrites a use of `transformer` with the first rule whose pattern matches.
+    fn transcribe(&mut self, transformer: &Macro, expr: &Expression, scope: &Rc<Scope>) -> Result<Argument> {
+        let form = Argument::Expression(expr.clone());
+        let (inputs, input_tail) = items(&form).unwrap();
+        for (pattern, template) in &transformer.rules {
+            let (patterns, pattern_tail) = items(pattern).unwrap();
+            let mut bindings = Bindings::new();
+            let matched = self.match_list(
+                transformer,
+                &patterns[1..],
+                pattern_tail,
+                &inputs[1..],
+                input_tail,
+                scope,
+                &expr.span,
+                &mut bindings,
+            );
+            if matched {
+                let mut renames = HashMap::new();
+                return self.instantiate(transformer, template, &bindings, &mut renames, &expr.span, false);
+            }
+        }
+        Err(bad_syntax(&transformer.name))
+    }
+
+    fn is_ellipsis(transformer: &Macro, arg: &Argument) -> bool {
+        symbol_name(arg).is_some_and(|id| base_name(id) == transformer.ellipsis)
+    }
+
+    fn match_pattern(
+        &self,
+        transformer: &Macro,
+        pattern: &Argument,
+        input: &Argument,
+        scope: &Rc<Scope>,
+        bindings: &mut Bindings,
+    ) -> bool {
+        if let Some(id) = symbol_name(pattern) {
+            if base_name(id) == "_" {
+                return true;
+            }
+            if transformer.literals.iter().any(|literal| &**literal == id) {
+                let Some(input) = symbol_name(input) else {
+                    return false;
+                };
+                return match (self.resolve(input, scope), self.resolve(id, &transformer.env)) {
+                    (Meaning::Variable(a), Meaning::Variable(b)) => a == b,
+                    (Meaning::Macro(a), Meaning::Macro(b)) => Rc::ptr_eq(&a, &b),
+                    _ => false,
+                };
+            }
+            bindings.insert(Rc::from(id), Match::One(input.clone()));
+            return true;
+        }
+        match (pattern, input) {
+            (Argument::Expression(_), _) => match (items(pattern), items(input)) {
+                (Some((patterns, pattern_tail)), Some((inputs, input_tail))) => self.match_list(
+                    transformer,
+                    &patterns,
+                    pattern_tail,
+                    &inputs,
+                    input_tail,
+                    scope,
+                    input.span(),
+                    bindings,
+                ),
+                _ => false,
+            },
+            // #(pattern ...) matches a vector whose elements match as a list would.
+            (Argument::LiteralVariable(LiteralVariable { value: Value::Vector(_), .. }), _) => {
+                let (Some(patterns), Some(inputs)) = (vector_items(pattern), vector_items(input)) else {
+                    return false;
+                };
+                self.match_list(
+                    transformer,
+                    &patterns.iter().collect::<Vec<_>>(),
+                    None,
+                    &inputs.iter().collect::<Vec<_>>(),
+                    None,
+                    scope,
+                    input.span(),
+                    bindings,
+                )
+            }
+            (Argument::LiteralVariable(pattern), Argument::LiteralVariable(input)) => {
+                same_datum(&pattern.value, &input.value)
+            }
+            _ => false,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn match_list(
+        &self,
+        transformer: &Macro,
+        patterns: &[&Argument],
+        pattern_tail: Option<&Argument>,
+        inputs: &[&Argument],
+        input_tail: Option<&Argument>,
+        scope: &Rc<Scope>,
+        span: &Span,
+        bindings: &mut Bindings,
+    ) -> bool {
+        let ellipsis = patterns
+            .iter()
+            .skip(1)
+            .position(|pattern| Self::is_ellipsis(transformer, pattern))
+            .map(|i| i + 1);
+        let (before, repeated, after) = match ellipsis {
+            Some(i) => (&patterns[..i - 1], Some(patterns[i - 1]), &patterns[i + 1..]),
+            None => (patterns, None, &[][..]),
+        };
+        let fixed = before.len() + after.len();
+        if inputs.len() < fixed || (repeated.is_none() && pattern_tail.is_none() && inputs.len() != fixed) {
+            return false;
+        }
+        if pattern_tail.is_none() && input_tail.is_some() {
+            return false;
+        }
+
+        for (pattern, input) in before.iter().zip(inputs) {
+            if !self.match_pattern(transformer, pattern, input, scope, bindings) {
+                return false;
+            }
+        }
+        let mut rest = &inputs[before.len()..];
+        if let Some(repeated) = repeated {
+            let (repeats, tail) = rest.split_at(rest.len() - after.len());
+            let mut matches = Vec::new();
+            for input in repeats {
+                let mut repetition = Bindings::new();
+                if !self.match_pattern(transformer, repeated, input, scope, &mut repetition) {
+                    return false;
+                }
+                matches.push(repetition);
+            }
+            for id in self.pattern_variables(transformer, repeated) {
+                let each = matches.iter_mut().map(|matched| matched.remove(&id).unwrap()).collect();
+                bindings.insert(id, Match::Many(each));
+            }
+            for (pattern, input) in after.iter().zip(tail) {
+                if !self.match_pattern(transformer, pattern, input, scope, bindings) {
+                    return false;
+                }
+            }
+            rest = &[];
+        }
+
+        match pattern_tail {
+            Some(pattern_tail) => {
+                let remaining = list(rest.iter().map(|arg| (*arg).clone()).collect(), input_tail.cloned(), span);
+                self.match_pattern(transformer, pattern_tail, &remaining, scope, bindings)
+            }
+            None => true,
+        }
+    }
+
+    /// The pattern variables bound by `pattern`.
+    fn pattern_variables(&self, transformer: &Macro, pattern: &Argument) -> Vec<Rc<str>> {
+        match pattern {
+            Argument::LiteralVariable(literal) => match &literal.value {
+                Value::Symbol(id)
+                    if base_name(id) != "_"
+                        && base_name(id) != transformer.ellipsis
+                        && !transformer.literals.contains(id) =>
+                {
+                    vec![id.clone()]
+                }
+                Value::Vector(_) => vector_items(pattern)
+                    .unwrap()
+                    .iter()
+                    .flat_map(|pattern| self.pattern_variables(transformer, pattern))
+                    .collect(),
+                _ => Vec::new(),
+            },
+            Argument::Expression(_) => {
+                let (patterns, tail) = items(pattern).unwrap();
+                patterns
+                    .into_iter()
+                    .chain(tail)
+                    .flat_map(|pattern| self.pattern_variables(transformer, pattern))
+                    .collect()
+            }
+        }
+    }
+
+    /// Fills in `template`. Identifiers that are not pattern variables are
+    /// renamed, consistently within this one expansion.
+    fn instantiate(
+        &mut self,
+        transformer: &Macro,
+        template: &Argument,
+        bindings: &Bindings,
+        renames: &mut HashMap<Rc<str>, Rc<str>>,
+        span: &Span,
+        escaped: bool,
+    ) -> Result<Argument> {
+        match template {
+            Argument::LiteralVariable(literal) => match &literal.value {
+                Value::Symbol(id) => match bindings.get(id) {
+                    Some(Match::One(arg)) => Ok(arg.clone()),
+                    Some(Match::Many(_)) => Err(Error::Parse(format!(
+                        "{}: pattern variable '{}' used without an ellipsis",
+                        transformer.name,
+                        base_name(id)
+                    ))),
+                    None => Ok(symbol(&self.alias(id, transformer, renames), span)),
+                },
+                // #(template ...) is filled in as a list would be; its elements are data.
+                Value::Vector(_) => {
+                    let elements = list(vector_items(template).unwrap(), None, span);
+                    let elements = self.instantiate(transformer, &elements, bindings, renames, span, escaped)?;
+                    let (elements, _) = items(&elements).unwrap();
+                    Ok(Argument::LiteralVariable(LiteralVariable {
+                        value: Value::vector(elements.into_iter().map(|element| strip(element).to_datum()).collect()),
+                        span: span.clone(),
+                    }))
+                }
+                value => Ok(Argument::LiteralVariable(LiteralVariable {
+                    value: value.clone(),
+                    span: span.clone(),
+                })),
+            },
+            Argument::Expression(_) => {
+                let (parts, tail) = items(template).unwrap();
+                // (... template) escapes the ellipsis inside template
+                if !escaped && parts.len() == 2 && tail.is_none() && Self::is_ellipsis(transformer, parts[0]) {
+                    return self.instantiate(transformer, parts[1], bindings, renames, span, true);
+                }
+                let mut out = Vec::new();
+                let mut i = 0;
+                while i < parts.len() {
+                    let mut depth = 0;
+                    while !escaped
+                        && parts
+                            .get(i + 1 + depth)
+                            .is_some_and(|part| Self::is_ellipsis(transformer, part))
+                    {
+                        depth += 1;
+                    }
+                    match depth {
+                        0 => out.push(self.instantiate(transformer, parts[i], bindings, renames, span, escaped)?),
+                        _ => out.extend(self.repeat(transformer, parts[i], depth, bindings, renames, span)?),
+                    }
+                    i += 1 + depth;
+                }
+                let tail = match tail {
+                    Some(tail) => Some(self.instantiate(transformer, tail, bindings, renames, span, escaped)?),
+                    None => None,
+                };
+                Ok(list(out, tail, span))
+            }
+        }
+    }
+
+    /// Instantiates `template` followed by `depth` ellipses, once for each
+    /// repetition of the pattern variables inside it.
+    fn repeat(
+        &mut self,
+        transformer: &Macro,
+        template: &Argument,
+        depth: usize,
+        bindings: &Bindings,
+        renames: &mut HashMap<Rc<str>, Rc<str>>,
+        span: &Span,
+    ) -> Result<Vec<Argument>> {
+        let repeated = self
+            .pattern_variables(transformer, template)
+            .into_iter()
+            .filter_map(|id| match bindings.get(&id) {
+                Some(Match::Many(matches)) => Some((id, matches.clone())),
+                _ => None,
+            })
+            .collect::<Vec<(Rc<str>, Vec<Match>)>>();
+        let Some(count) = repeated.first().map(|(_, matches)| matches.len()) else {
+            return Err(Error::Parse(format!(
+                "{}: no pattern variable to repeat before '{}' in template",
+                transformer.name, transformer.ellipsis
+            )));
+        };
+        if repeated.iter().any(|(_, matches)| matches.len() != count) {
+            return Err(Error::Parse(format!(
+                "{}: pattern variables repeat different numbers of times",
+                transformer.name
+            )));
+        }
+
+        let mut out = Vec::new();
+        for i in 0..count {
+            let mut repetition = bindings.clone();
+            for (id, matches) in &repeated {
+                repetition.insert(id.clone(), matches[i].clone());
+            }
+            match depth {
+                1 => out.push(self.instantiate(transformer, template, &repetition, renames, span, false)?),
+                _ => out.extend(self.repeat(transformer, template, depth - 1, &repetition, renames, span)?),
+            }
+        }
+        Ok(out)
+    }
+
+    /// The alias standing for `id` from `transformer`'s template in this expansion.
+    fn alias(&mut self, id: &Rc<str>, transformer: &Macro, renames: &mut HashMap<Rc<str>, Rc<str>>) -> Rc<str> {
+        if let Some(alias) = renames.get(id) {
+            return alias.clone();
+        }
+        self.expander.aliases.push((id.clone(), transformer.env.clone()));
+        let alias: Rc<str> = Rc::from(format!("{}{}{}", base_name(id), ALIAS, self.expander.aliases.len() - 1));
+        renames.insert(id.clone(), alias.clone());
+        alias
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::eval;
+
+    #[test]
+    fn globals_a_macro_defines_are_renamed() {
+        let source = "(define tmp 'mine)
+                      (define-syntax remember
+                        (syntax-rules ()
+                          ((_ recall value) (begin (define tmp value) (define (recall) tmp)))))
+                      (remember recall 'theirs)
+                      (list tmp (recall))";
+        assert_eq!(eval(source).unwrap(), "(mine theirs)");
+        let source = "(define-syntax define-getter
+                        (syntax-rules () ((_ name value) (define (name) value))))
+                      (define-getter answer 42)
+                      (answer)";
+        assert_eq!(eval(source).unwrap(), "42");
+    }
+
+    #[test]
+    fn vector_patterns_match_vectors() {
+        let source = "(define-syntax sum-vector
+                        (syntax-rules () ((_ #(first rest ...)) (+ first rest ...)) ((_ other) 'not-a-vector)))
+                      (list (sum-vector #(1 2 3)) (sum-vector #(5)) (sum-vector (1 2)))";
+        assert_eq!(eval(source).unwrap(), "(6 5 not-a-vector)");
+        let source = "(define-syntax pairs
+                        (syntax-rules () ((_ #((a b) ...)) (list (cons 'a 'b) ...))))
+                      (pairs #((x 1) (y 2)))";
+        assert_eq!(eval(source).unwrap(), "((x . 1) (y . 2))");
+        let source = "(define-syntax firsts
+                        (syntax-rules () ((_ #((a b) ...)) '(a ...))))
+                      (firsts #((x 1) (y 2)))";
+        assert_eq!(eval(source).unwrap(), "(x y)");
+    }
+
+    #[test]
+    fn vector_templates_are_filled_in() {
+        let source = "(define-syntax twice (syntax-rules () ((_ x) '#(x x))))
+                      (define-syntax all (syntax-rules () ((_ x ...) `#(start x ... end))))
+                      (list (twice 1) (all 1 2 3) (all))";
+        assert_eq!(eval(source).unwrap(), "(#(1 1) #(start 1 2 3 end) #(start end))");
+    }
+
+    #[test]
+    fn begins_in_bodies_are_spliced() {
+        let source = "(define-syntax def2 (syntax-rules () ((_ a b v) (begin (define a v) (define b v)))))
+                      (define (f) (def2 x y 1) (+ x y))
+                      (define (g) (begin (define z 2) (begin (define w z))) (list z w))
+                      (define (h) (if #t (begin (define z 3) z)))
+                      (list (f) (g) (h) (let () (def2 p q 4) (* p q)))";
+        assert_eq!(eval(source).unwrap(), "(2 (2 2) 3 16)");
+    }
+
+    #[test]
+    fn gensyms_name_their_own_variables() {
+        let source = "(define-macro (swap! a b)
+                        (let ((tmp (gensym))) `(let ((,tmp ,a)) (set! ,a ,b) (set! ,b ,tmp))))
+                      (define-macro (define-hidden value)
+                        (let ((name (gensym))) `(begin (define ,name ,value) (define hidden ',name))))
+                      (define-hidden 7)
+                      (define-macro (original) hidden)
+                      (define-macro (lookalike) (string->symbol (symbol->string hidden)))
+                      (define x 1)
+                      (define y 2)
+                      (swap! x y)
+                      (list x y (original))";
+        assert_eq!(eval(source).unwrap(), "(2 1 7)");
+        let error = eval(&format!("{} (lookalike)", source)).unwrap_err();
+        assert!(error.to_string().starts_with("unbound variable 'g@"), "{}", error);
+    }
+
+    #[test]
+    fn quasiquote_does_not_depend_on_redefinable_globals() {
+        let source = "(define (cons a b) 0)
+                      (define (append . lists) 0)
+                      (define (list->vector list) 0)
+                      (define x 5)
+                      `(1 ,x ,@(list 2 3) #(a ,x))";
+        assert_eq!(eval(source).unwrap(), "(1 5 2 3 #(a 5))");
+    }
+
+    #[test]
+    fn symbols_that_look_like_aliases_are_plain_variables() {
+        let error = eval("(define-macro (m) (string->symbol \"a\\x1;99\")) (m)").unwrap_err();
+        assert!(error.to_string().starts_with("unbound variable 'a"), "{}", error);
+    }
+}
@@ -1,13 +1,23 @@
 #[path = "types.rs"]
 pub(crate) mod types;
 
-use core::panic;
+use std::rc::Rc;
 
 use types::*;
 
-#[derive(Debug, Clone)]
+use crate::error::{Error, Result};
+use crate::span::{Source, Span};
+use crate::value::CHAR_NAMES;
+
+/// Whether `c` ends the token before it; `'\0'` is the end of input.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';' | '\0')
+}
+
+#[derive(Clone)]
 pub struct Lexer {
-    pub input: String,
+    pub source: Rc<Source>,
+    pub input: Vec<char>,
     pub position: usize,
     pub row: usize,
     pub column: usize,
@@ -15,101 +25,148 @@ pub struct Lexer {
 }
 
 impl Lexer {
-    pub fn new(input: &String) -> Self {
+    pub fn new(input: &str, name: &str) -> Self {
         Self {
-            input: input.to_string(),
+            source: Source::new(name, input),
+            input: input.chars().collect(),
             position: 0,
             row: 1,
-            column: 1,
+            column: 0,
             ch: ' ',
         }
     }
 
-    pub fn next_token(&mut self) -> LexerToken {
-        let token: Tokens;
-        let value: String;
+    pub fn next_token(&mut self) -> Result<LexerToken> {
         self.next_char();
 
         loop {
             match self.ch {
-                ' ' | '\t' | '\n' | '\r' => {
-                    self.skip_whitespace();
-                }
-                'a'..='z' | 'A'..='Z' | '#' => {
-                    value = self.read_identifier();
-                    token = self.lookup_identifier(&value);
-                    return LexerToken {
-                        token,
-                        var_type: Types::Keyword,
-                        value: Some(value),
-                    };
-                }
-                '0'..='9' => {
-                    value = self.read_number();
-                    let var_type = self.get_numbervar_type(value.clone());
-                    token = self.read_numbervar_type(value.clone());
-                    return LexerToken {
-                        token,
-                        var_type,
-                        value: Some(value.clone()),
-                    };
-                }
-                '(' | ')' => {
-                    token = self.read_punctuation(self.ch);
-                    return LexerToken {
-                        token,
-                        var_type: Types::Unknown,
-                        value: Some(self.ch.to_string()),
-                    };
-                }
-                '"' => {
-                    value = self.read_string();
-                    token = Tokens::Var(Types::String);
-                    return LexerToken {
-                        var_type: Types::String,
-                        token,
-                        value: Some(value.clone()),
-                    };
-                }
+                ' ' | '\t' | '\n' | '\r' => self.skip_whitespace(),
+                ';' => self.skip_comment(),
+                _ => break,
+            }
+        }
 
-                '+' | '-' | '*' | '/' | '=' | '>' | '<' | '%' | '!' => {
-                    let peeked = self.peek_char();
-
-                    match peeked {
-                        '=' => {
-                            let mut double_operator = String::new();
-                            double_operator.push(self.ch);
-                            double_operator.push(peeked);
-                            self.next_char();
-
-                            token = self.read_double_operator(&double_operator);
-                            return LexerToken {
-                                var_type: Types::Unknown,
-                                token,
-                                value: Some(double_operator),
-                            };
-                        }
-                        _ => {
-                            token = self.read_single_operator(self.ch);
-                            return LexerToken {
-                                var_type: Types::Unknown,
-                                token,
-                                value: Some(self.ch.to_string()),
-                            };
-                        }
-                    };
-                }
-                ';' => {
-                    self.skip_comment();
+        let (row, column) = (self.row, self.column);
+        let (token, var_type, value) = self.read_token()?;
+        Ok(LexerToken {
+            var_type,
+            token,
+            value,
+            span: self.span_from(row, column),
+        })
+    }
+
+    /// The span from `(row, column)` up to and including the current character.
+    fn span_from(&self, row: usize, column: usize) -> Span {
+        Span {
+            source: self.source.clone(),
+            line: row,
+            column,
+            end_line: self.row,
+            end_column: self.column.max(1),
+        }
+    }
+
+    fn error(&self, message: String) -> Error {
+        Error::Lex(message).at(&self.span_from(self.row, self.column))
+    }
+
+    fn read_token(&mut self) -> Result<(Tokens, Types, Option<String>)> {
+        let value: String;
+        match self.ch {
+            '#' if self.peek_char() == '(' => {
+                self.next_char();
+                Ok((
+                    Tokens::Punctuation(Punctuations::VectorOpen),
+                    Types::Unknown,
+                    Some("#(".to_string()),
+                ))
+            }
+            '#' if self.peek_char() == '\\' => {
+                value = self.read_character()?;
+                Ok((Tokens::Var(Types::Char), Types::Char, Some(value)))
+            }
+            'a'..='z' | 'A'..='Z' | '#' | '_' | '$' | '&' | ':' | '?' | '^' | '~' => {
+                value = self.read_identifier();
+                let token = self.lookup_identifier(&value);
+                let var_type = match token {
+                    Tokens::Var(Types::Bool) => Types::Bool,
+                    _ => Types::Keyword,
+                };
+                Ok((token, var_type, Some(value)))
+            }
+            '0'..='9' => {
+                value = self.read_number();
+                let var_type = self.get_numbervar_type(value.clone());
+                let token = self.read_numbervar_type(value.clone());
+                Ok((token, var_type, Some(value)))
+            }
+            '(' | ')' => {
+                let token = self.read_punctuation(self.ch);
+                Ok((token, Types::Unknown, Some(self.ch.to_string())))
+            }
+            '\'' => Ok((
+                Tokens::Punctuation(Punctuations::Quote),
+                Types::Unknown,
+                Some("'".to_string()),
+            )),
+            '`' => Ok((
+                Tokens::Punctuation(Punctuations::Quasiquote),
+                Types::Unknown,
+                Some("`".to_string()),
+            )),
+            ',' if self.peek_char() == '@' => {
+                self.next_char();
+                Ok((
+                    Tokens::Punctuation(Punctuations::UnquoteSplicing),
+                    Types::Unknown,
+                    Some(",@".to_string()),
+                ))
+            }
+            ',' => Ok((
+                Tokens::Punctuation(Punctuations::Unquote),
+                Types::Unknown,
+                Some(",".to_string()),
+            )),
+            '"' => {
+                value = self.read_string()?;
+                Ok((Tokens::Var(Types::String), Types::String, Some(value)))
+            }
+            '.' => {
+                let peeked = self.peek_char();
+                if peeked.is_ascii_digit() {
+                    value = self.read_number();
+                    return Ok((Tokens::Var(Types::Float), Types::Float, Some(value)));
                 }
-                _ => {
-                    return LexerToken {
-                        var_type: Types::Unknown,
-                        token: Tokens::Eof,
-                        value: None,
-                    }
+                if peeked.is_whitespace() || peeked == '(' || peeked == ')' || peeked == '\0' {
+                    return Ok((
+                        Tokens::Punctuation(Punctuations::Dot),
+                        Types::Unknown,
+                        Some(".".to_string()),
+                    ));
                 }
-            };
+                value = self.read_identifier();
+                Ok((Tokens::Var(Types::Unknown), Types::Keyword, Some(value)))
+            }
+            '+' | '-' if self.peek_char().is_ascii_digit() => {
+                value = self.read_number();
+                let var_type = self.get_numbervar_type(value.clone());
+                let token = self.read_numbervar_type(value.clone());
+                Ok((token, var_type, Some(value)))
+            }
+            '+' | '-' | '*' | '/' | '=' | '>' | '<' | '%' | '!' => {
+                // Operator characters can also start a longer symbol such as `=>`.
+                value = self.read_identifier();
+                let token = match value.as_str() {
+                    "<=" | ">=" | "!=" | "==" => self.read_double_operator(&value)?,
+                    _ if value.len() == 1 => self.read_single_operator(self.ch)?,
+                    _ => Tokens::Var(Types::Unknown),
+                };
+                Ok((token, Types::Unknown, Some(value)))
+            }
+            '\0' => Ok((Tokens::Eof, Types::Unknown, None)),
+            _ => Err(self.error(format!("unexpected character '{}'", self.ch))),
         }
     }
 
@@ -117,110 +174,137 @@ impl Lexer {
         if self.position >= self.input.len() {
             '\0'
         } else {
-            self.input.chars().nth(self.position).unwrap()
+            self.input[self.position]
         }
     }
 
-    fn next_char(&mut self) -> Self {
-        if self.ch == '\0' {
-            panic!("End of file");
+    fn next_char(&mut self) {
+        if self.ch == '\n' {
+            self.row += 1;
+            self.column = 0;
         }
+
         if self.position >= self.input.len() {
             self.ch = '\0';
         } else {
-            self.ch = self.input.chars().nth(self.position).unwrap();
+            self.ch = self.input[self.position];
         }
 
         self.position += 1;
         self.column += 1;
-
-        if self.ch == '\n' {
-            self.row += 1;
-            self.column = 1;
-        }
-        self.clone()
     }
 
-    fn skip_whitespace(&mut self) -> Self {
+    fn skip_whitespace(&mut self) {
         while self.ch == ' ' || self.ch == '\t' || self.ch == '\n' || self.ch == '\r' {
             self.next_char();
         }
-        self.clone()
     }
 
     fn read_identifier(&mut self) -> String {
         let mut identifier = String::new();
-        while self.peek_char().is_alphanumeric() || self.peek_char() == '-' {
+        while self.peek_char().is_alphanumeric() || "!$%&*/:<=>?^_~+-.".contains(self.peek_char()) {
             identifier.push(self.ch);
             self.next_char();
         }
         identifier.push(self.ch);
-
-        match identifier.as_str() {
-            "true" => return "0".to_string(),
-            "false " => return "0".to_string(),
-            _ => (),
-        };
         identifier
     }
 
+    /// Reads `#\a`, `#\space` or `#\x41`, returning the character itself.
+    fn read_character(&mut self) -> Result<String> {
+        let (row, column) = (self.row, self.column);
+        self.next_char();
+        self.next_char();
+        if self.ch == '\0' {
+            return Err(Error::Incomplete("unterminated character literal".to_string())
+                .at(&self.span_from(row, column)));
+        }
+
+        let mut name = self.ch.to_string();
+        if self.ch.is_alphabetic() {
+            while self.peek_char().is_alphanumeric() {
+                self.next_char();
+                name.push(self.ch);
+            }
+        }
+        if name.chars().count() == 1 {
+            return Ok(name);
+        }
+
+        let named = CHAR_NAMES.iter().find(|(known, _)| *known == name).map(|(_, c)| *c);
+        let hex = name
+            .strip_prefix('x')
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .and_then(char::from_u32);
+        match named.or(hex) {
+            Some(c) => Ok(c.to_string()),
+            None => Err(Error::Lex(format!("unknown character name '#\\{}'", name))
+                .at(&self.span_from(row, column))),
+        }
+    }
+
+    /// A number runs up to the next delimiter; the parser rejects one with
+    /// anything else in it, such as `1/2/3` or `1/2x`, as malformed.
     fn read_number(&mut self) -> String {
         let mut number = String::new();
         number.push(self.ch);
 
-        let mut peeked = self.peek_char();
-
-        while peeked.is_numeric() || peeked == '.' {
+        while !is_delimiter(self.peek_char()) {
             self.next_char();
             number.push(self.ch);
-            peeked = self.peek_char();
         }
         number
     }
 
     fn read_numbervar_type(&self, number: String) -> Tokens {
+        if number.contains("/") {
+            return Tokens::Var(Types::Rational);
+        }
         if number.contains(".") {
             return Tokens::Var(Types::Float);
         }
-        return Tokens::Var(Types::Int);
+        Tokens::Var(Types::Int)
     }
 
     fn read_punctuation(&mut self, ch: char) -> Tokens {
         match ch {
-            '(' => return Tokens::Punctuation(Punctuations::LParen),
-            ')' => return Tokens::Punctuation(Punctuations::RParen),
-            _ => panic!("Unknown punctuation"),
+            '(' => Tokens::Punctuation(Punctuations::LParen),
+            _ => Tokens::Punctuation(Punctuations::RParen),
         }
     }
 
-    fn read_single_operator(&self, ch: char) -> Tokens {
+    fn read_single_operator(&self, ch: char) -> Result<Tokens> {
         match ch {
-            '+' => return Tokens::Operator(Operators::Plus),
-            '-' => return Tokens::Operator(Operators::Minus),
-            '*' => return Tokens::Operator(Operators::Asterisk),
-            '/' => return Tokens::Operator(Operators::Slash),
-            '<' => return Tokens::Operator(Operators::Lt),
-            '>' => return Tokens::Operator(Operators::Gt),
-            '=' => return Tokens::Operator(Operators::Eq),
-            '%' => return Tokens::Operator(Operators::Percent),
-            _ => panic!("Unknown operator"),
+            '+' => Ok(Tokens::Operator(Operators::Plus)),
+            '-' => Ok(Tokens::Operator(Operators::Minus)),
+            '*' => Ok(Tokens::Operator(Operators::Asterisk)),
+            '/' => Ok(Tokens::Operator(Operators::Slash)),
+            '<' => Ok(Tokens::Operator(Operators::Lt)),
+            '>' => Ok(Tokens::Operator(Operators::Gt)),
+            '=' => Ok(Tokens::Operator(Operators::Eq)),
+            '%' => Ok(Tokens::Operator(Operators::Percent)),
+            _ => Err(self.error(format!("unknown operator '{}'", ch))),
         }
     }
 
-    fn read_double_operator(&self, ch: &str) -> Tokens {
+    fn read_double_operator(&self, ch: &str) -> Result<Tokens> {
         match ch {
-            "<=" => return Tokens::Operator(Operators::Le),
-            ">=" => return Tokens::Operator(Operators::Ge),
-            "!=" => return Tokens::Operator(Operators::Ne),
-            _ => panic!("Unknown operator"),
+            "<=" => Ok(Tokens::Operator(Operators::Le)),
+            ">=" => Ok(Tokens::Operator(Operators::Ge)),
+            "!=" => Ok(Tokens::Operator(Operators::Ne)),
+            "==" => Ok(Tokens::Operator(Operators::Eq)),
+            _ => Err(self.error(format!("unknown operator '{}'", ch))),
         }
     }
 
     fn get_numbervar_type(&mut self, number: String) -> Types {
+        if number.contains("/") {
+            return Types::Rational;
+        }
         if number.contains(".") {
             return Types::Float;
         }
-        return Types::Int;
+        Types::Int
     }
 
     fn lookup_identifier(&self, identifier: &str) -> Tokens {
@@ -232,10 +316,9 @@ impl Lexer {
             "read" => Tokens::Keyword(Keywords::Read),
             "read-line" => Tokens::Keyword(Keywords::ReadLine),
             "begin" => Tokens::Keyword(Keywords::Begin),
-            "false" => Tokens::Var(Types::Bool),
+            "#t" | "#f" | "#true" | "#false" => Tokens::Var(Types::Bool),
             "then" => Tokens::Keyword(Keywords::Then),
             "else" => Tokens::Keyword(Keywords::Else),
-            "true" => Tokens::Var(Types::Bool),
             "end" => Tokens::Keyword(Keywords::End),
             "if" => Tokens::Keyword(Keywords::If),
             _ => Tokens::Var(Types::Unknown),
@@ -243,22 +326,119 @@ impl Lexer {
     }
 
     fn skip_comment(&mut self) {
-        while self.ch != '\n' {
+        while self.ch != '\n' && self.ch != '\0' {
             self.next_char();
         }
 
         self.skip_whitespace();
     }
 
-    fn read_string(&mut self) -> String {
+    fn read_string(&mut self) -> Result<String> {
+        let (row, column) = (self.row, self.column);
         let mut string = String::new();
         self.next_char();
 
         while self.ch != '"' {
-            string.push(self.ch);
+            match self.ch {
+                '\0' => {
+                    return Err(Error::Incomplete("unterminated string literal".to_string())
+                        .at(&self.span_from(row, column)))
+                }
+                '\\' => {
+                    let (escape_row, escape_column) = (self.row, self.column);
+                    self.next_char();
+                    if let Some(c) = self.read_escape(escape_row, escape_column)? {
+                        string.push(c);
+                    }
+                }
+                c => string.push(c),
+            }
             self.next_char();
         }
 
-        string
+        Ok(string)
+    }
+
+    /// Decodes the escape whose backslash is at `(row, column)`; the current
+    /// character is the one after it. A line continuation decodes to nothing.
+    fn read_escape(&mut self, row: usize, column: usize) -> Result<Option<char>> {
+        let c = match self.ch {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'a' => '\x07',
+            'b' => '\x08',
+            '0' => '\0',
+            '\\' | '"' | '|' => self.ch,
+            'x' | 'X' => {
+                let mut digits = String::new();
+                while self.peek_char().is_ascii_hexdigit() {
+                    self.next_char();
+                    digits.push(self.ch);
+                }
+                self.next_char();
+                let c = u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32);
+                match c {
+                    Some(c) if self.ch == ';' => c,
+                    _ => {
+                        return Err(Error::Lex("malformed '\\x' escape, expected '\\x<hex>;'".to_string())
+                            .at(&self.span_from(row, column)))
+                    }
+                }
+            }
+            ' ' | '\t' | '\r' | '\n' => {
+                while self.ch == ' ' || self.ch == '\t' {
+                    self.next_char();
+                }
+                if self.ch == '\r' && self.peek_char() == '\n' {
+                    self.next_char();
+                }
+                if self.ch != '\n' {
+                    return Err(Error::Lex("expected a line break after '\\' in string".to_string())
+                        .at(&self.span_from(row, column)));
+                }
+                while self.peek_char() == ' ' || self.peek_char() == '\t' {
+                    self.next_char();
+                }
+                return Ok(None);
+            }
+            '\0' => {
+                return Err(Error::Incomplete("unterminated string literal".to_string())
+                    .at(&self.span_from(row, column)))
+            }
+            other => {
+                return Err(Error::Lex(format!("unknown escape '\\{}' in string", other))
+                    .at(&self.span_from(row, column)))
+            }
+        };
+        Ok(Some(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::eval;
+
+    #[test]
+    fn character_literals() {
+        assert_eq!(eval("(list #\\a #\\space #\\newline #\\x41 #\\( #\\x3bb)").unwrap(), "(#\\a #\\space #\\newline #\\A #\\( #\\λ)");
+        assert_eq!(eval("(list (char->integer #\\A) (integer->char 97) (char-upcase #\\b))").unwrap(), "(65 #\\a #\\B)");
+        assert_eq!(eval("(list (char-alphabetic? #\\a) (char-numeric? #\\7) (char-whitespace? #\\tab) (char<? #\\a #\\b #\\c))").unwrap(), "(#t #t #t #t)");
+        assert!(eval("#\\bogus").unwrap_err().to_string().starts_with("unknown character name '#\\bogus'"));
+    }
+
+    #[test]
+    fn string_escapes_are_decoded_when_lexing() {
+        assert_eq!(eval(r#"(string-length "a\"b\\c\n\t\r")"#).unwrap(), "8");
+        assert_eq!(eval(r#"(string->list "\x41;\x3bb;")"#).unwrap(), "(#\\A #\\λ)");
+        assert_eq!(eval("\"one \\\n     two\"").unwrap(), "\"one two\"");
+        assert!(eval(r#""\q""#).unwrap_err().to_string().starts_with("unknown escape '\\q' in string"));
+    }
+
+    #[test]
+    fn numbers_must_end_at_a_delimiter() {
+        assert_eq!(eval("(list 1/2 -3 .5 (+ 1(* 2 3)))").unwrap(), "(1/2 -3 0.5 7)");
+        assert!(eval("1/2/3").unwrap_err().to_string().starts_with("malformed number '1/2/3'"));
+        assert!(eval("(list 1/2x)").unwrap_err().to_string().starts_with("malformed number '1/2x'"));
     }
 }
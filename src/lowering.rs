@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+use crate::compiler::{bad_syntax, defined_name, expect_args, symbol_name};
+use crate::error::{Error, Result};
+use crate::native::{FIXNUM_MAX, FIXNUM_MIN};
+use crate::parser::{Argument, Expression};
+use crate::span::Span;
+use crate::value::Value;
+
+/// Built-in procedures the backends can call: (name, runtime function,
+/// minimum and maximum argument counts).
+const BUILTINS: &[(&str, &str, usize, Option<usize>)] = &[
+    ("display", "scm_display", 0, None),
+    ("newline", "scm_newline", 0, Some(0)),
+    ("read", "scm_read", 0, Some(0)),
+    ("read-line", "scm_read_line", 0, Some(0)),
+    ("+", "scm_add", 0, None),
+    ("-", "scm_sub", 1, None),
+    ("*", "scm_mul", 0, None),
+    ("/", "scm_div", 1, None),
+    ("%", "scm_rem", 2, Some(2)),
+    ("<", "scm_lt", 2, Some(2)),
+    ("<=", "scm_le", 2, Some(2)),
+    (">", "scm_gt", 2, Some(2)),
+    (">=", "scm_ge", 2, Some(2)),
+    ("=", "scm_eq", 2, Some(2)),
+    ("!=", "scm_ne", 2, Some(2)),
+];
+
+pub fn builtin(name: &str) -> Option<&'static (&'static str, &'static str, usize, Option<usize>)> {
+    BUILTINS.iter().find(|builtin| builtin.0 == name)
+}
+
+/// Checks a call to the built-in `name`; returns the runtime function to call.
+pub fn check_builtin(name: &str, expr: &Expression) -> Result<&'static str> {
+    let &(_, function, min_args, max_args) = builtin(name).unwrap();
+    let found = expr.arguments.len();
+    if expr.rest.is_some() {
+        return Err(bad_syntax("application"));
+    }
+    if found < min_args || max_args.is_some_and(|max| found > max) {
+        let expected = match max_args {
+            Some(max) if max == min_args => max.to_string(),
+            Some(max) => format!("{} to {}", min_args, max),
+            None => format!("at least {}", min_args),
+        };
+        return Err(Error::Arity {
+            procedure: name.to_string(),
+            expected,
+            found,
+        });
+    }
+    Ok(function)
+}
+
+/// Rejects literals outside the compiled subset.
+pub fn check_literal(value: &Value) -> Result<()> {
+    let feature = match value {
+        Value::Int(n) if (FIXNUM_MIN..=FIXNUM_MAX).contains(n) => return Ok(()),
+        Value::Int(_) | Value::BigInt(_) => "big integer literals".to_string(),
+        Value::Rational(_) => "rational literals".to_string(),
+        Value::Float(_) => "floating-point literals".to_string(),
+        Value::Bool(_) | Value::String(_) | Value::Symbol(_) => return Ok(()),
+        value => format!("{} literals", value.type_name()),
+    };
+    Err(Error::Unsupported(feature))
+}
+
+/// Where a variable of a compiled program lives.
+#[derive(Debug, Clone, Copy)]
+pub enum Place {
+    /// A slot in the frame of `scheme_main`.
+    Local(usize),
+    Global(usize),
+}
+
+/// Resolves names to places. Globals are numbered in order of first use;
+/// the locals of a `begin` block take the next free frame slots, which are
+/// reused once the block ends.
+#[derive(Default)]
+pub struct Scopes {
+    globals: HashMap<String, usize>,
+    /// Names bound by each enclosing `begin` block, with their frame slots.
+    blocks: Vec<Vec<(String, usize)>>,
+    slots: usize,
+    /// The most frame slots in use at once.
+    pub frame_size: usize,
+}
+
+impl Scopes {
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.blocks.iter().rev().find_map(|block| {
+            let (_, slot) = block.iter().find(|(bound, _)| bound == name)?;
+            Some(*slot)
+        })
+    }
+
+    fn is_toplevel(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    fn enter(&mut self) {
+        self.blocks.push(Vec::new());
+    }
+
+    /// Ends the innermost block, freeing its slots.
+    fn leave(&mut self) {
+        self.slots -= self.blocks.pop().unwrap().len();
+    }
+
+    /// The slot of the global `name`, and whether this is its first use.
+    fn global(&mut self, name: &str) -> (usize, bool) {
+        let count = self.globals.len();
+        let index = *self.globals.entry(name.to_string()).or_insert(count);
+        (index, index == count)
+    }
+
+    /// The frame slot for `name` in the innermost block, and whether it was
+    /// just allocated.
+    fn local(&mut self, name: &str) -> (usize, bool) {
+        if let Some((_, slot)) = self.blocks.last().unwrap().iter().find(|(bound, _)| bound == name) {
+            return (*slot, false);
+        }
+        let slot = self.slots;
+        self.slots += 1;
+        self.frame_size = self.frame_size.max(self.slots);
+        self.blocks.last_mut().unwrap().push((name.to_string(), slot));
+        (slot, true)
+    }
+}
+
+/// The lowering both ahead-of-time backends share: which forms the subset
+/// has, and where each variable lives. A backend supplies the code for each
+/// primitive step; `Value` is what an expression lowers to.
+pub trait Backend {
+    type Value;
+
+    fn scopes(&mut self) -> &mut Scopes;
+    /// Emits the storage for a global, on the first use of `name`.
+    fn allocate_global(&mut self, index: usize, name: &str);
+    /// Emits the initialization of a frame slot as its block is entered.
+    fn initialize_local(&mut self, slot: usize);
+    fn unspecified(&mut self) -> Self::Value;
+    /// Marks a value as unused.
+    fn discard(&mut self, value: Self::Value);
+    /// A self-evaluating literal, already checked by `check_literal`.
+    fn constant(&mut self, value: &Value) -> Self::Value;
+    /// Reads a variable; reading an unbound global fails at `span`.
+    fn load(&mut self, place: Place, name: &str, span: &Span) -> Self::Value;
+    /// Stores `value` in a variable, binding it if needed.
+    fn store(&mut self, place: Place, value: Self::Value);
+    /// Stores `value` in a variable that must already be bound; storing to an
+    /// unbound global fails at `span`.
+    fn update(&mut self, place: Place, value: Self::Value, name: &str, span: &Span);
+    fn while_statement(&mut self, expr: &Expression) -> Result<Self::Value>;
+    fn if_statement(&mut self, expr: &Expression) -> Result<Self::Value>;
+    fn builtin_call(&mut self, name: &str, expr: &Expression) -> Result<Self::Value>;
+
+    fn global(&mut self, name: &str) -> Place {
+        let (index, new) = self.scopes().global(name);
+        if new {
+            self.allocate_global(index, name);
+        }
+        Place::Global(index)
+    }
+
+    /// The frame slot for `name` in the innermost block, allocating one if needed.
+    fn declare(&mut self, name: &str) -> Place {
+        let (slot, new) = self.scopes().local(name);
+        if new {
+            self.initialize_local(slot);
+        }
+        Place::Local(slot)
+    }
+
+    /// A top-level `begin` is spliced, so its definitions are globals.
+    fn toplevel(&mut self, arg: &Argument) -> Result<()> {
+        if let Argument::Expression(expr) = arg {
+            if symbol_name(&expr.function) == Some("begin") {
+                for arg in &expr.arguments {
+                    self.toplevel(arg)?;
+                }
+                return Ok(());
+            }
+        }
+        let value = self.expression(arg)?;
+        self.discard(value);
+        Ok(())
+    }
+
+    fn expression(&mut self, arg: &Argument) -> Result<Self::Value> {
+        match arg {
+            Argument::Expression(expr) => match symbol_name(&expr.function) {
+                Some("begin") => self.begin(expr),
+                Some("define") => self.define(expr),
+                Some("set!") => self.set(expr),
+                Some("while") => self.while_statement(expr),
+                Some("if") => self.if_statement(expr),
+                Some(
+                    form @ ("lambda" | "quote" | "let" | "let*" | "letrec" | "letrec*" | "cond" | "case"
+                    | "when" | "unless" | "and" | "or" | "define-syntax" | "let-syntax" | "letrec-syntax"
+                    | "define-macro" | "quasiquote"),
+                ) => Err(Error::Unsupported(form.to_string())),
+                Some(name) if builtin(name).is_some() && self.scopes().resolve(name).is_none() => {
+                    self.builtin_call(name, expr)
+                }
+                Some(name) => Err(Error::Unsupported(format!("calling '{}'", name))),
+                None => Err(Error::Unsupported("calling a computed procedure".to_string())),
+            }
+            .map_err(|error| error.at(&expr.span)),
+            Argument::LiteralVariable(literal) => self
+                .literal(&literal.value, &literal.span)
+                .map_err(|error| error.at(&literal.span)),
+        }
+    }
+
+    fn literal(&mut self, value: &Value, span: &Span) -> Result<Self::Value> {
+        check_literal(value)?;
+        let Value::Symbol(name) = value else {
+            return Ok(self.constant(value));
+        };
+        let place = match self.scopes().resolve(name) {
+            Some(slot) => Place::Local(slot),
+            None if builtin(name).is_some() => {
+                return Err(Error::Unsupported(format!("using '{}' as a value", name)))
+            }
+            None => self.global(name),
+        };
+        Ok(self.load(place, name, span))
+    }
+
+    /// Lowers a sequence; returns the value of the last form.
+    fn body(&mut self, body: &[Argument]) -> Result<Self::Value> {
+        match body.split_last() {
+            Some((last, init)) => {
+                for arg in init {
+                    let value = self.expression(arg)?;
+                    self.discard(value);
+                }
+                self.expression(last)
+            }
+            None => Ok(self.unspecified()),
+        }
+    }
+
+    /// A nested `begin` that defines anything gets slots of its own.
+    fn begin(&mut self, expr: &Expression) -> Result<Self::Value> {
+        if !expr.arguments.iter().any(|arg| defined_name(arg).is_some()) {
+            return self.body(&expr.arguments);
+        }
+
+        self.scopes().enter();
+        for name in expr.arguments.iter().filter_map(defined_name) {
+            self.declare(name);
+        }
+        let result = self.body(&expr.arguments)?;
+        self.scopes().leave();
+        Ok(result)
+    }
+
+    fn define(&mut self, expr: &Expression) -> Result<Self::Value> {
+        let args = &expr.arguments;
+        expect_args("define", args, 1, None)?;
+        if let Argument::Expression(_) = &args[0] {
+            return Err(Error::Unsupported("defining procedures".to_string()));
+        }
+        expect_args("define", args, 2, Some(2))?;
+        let name = symbol_name(&args[0]).ok_or_else(|| bad_syntax("define"))?;
+        if builtin(name).is_some() {
+            return Err(Error::Unsupported(format!("redefining '{}'", name)));
+        }
+
+        let value = self.expression(&args[1])?;
+        let place = match self.scopes().is_toplevel() {
+            true => self.global(name),
+            false => self.declare(name),
+        };
+        self.store(place, value);
+        Ok(self.unspecified())
+    }
+
+    fn set(&mut self, expr: &Expression) -> Result<Self::Value> {
+        let args = &expr.arguments;
+        expect_args("set!", args, 2, Some(2))?;
+        let name = symbol_name(&args[0]).ok_or_else(|| bad_syntax("set!"))?;
+        let value = self.expression(&args[1])?;
+        let place = match self.scopes().resolve(name) {
+            Some(slot) => Place::Local(slot),
+            None if builtin(name).is_some() => {
+                return Err(Error::Unsupported(format!("redefining '{}'", name)))
+            }
+            None => self.global(name),
+        };
+        self.update(place, value, name, args[0].span());
+        Ok(self.unspecified())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_slots_are_reused_once_the_block_ends() {
+        let mut scopes = Scopes::default();
+        assert_eq!(scopes.global("x"), (0, true));
+        assert_eq!(scopes.global("x"), (0, false));
+        scopes.enter();
+        assert_eq!(scopes.local("a"), (0, true));
+        scopes.enter();
+        assert_eq!(scopes.local("b"), (1, true));
+        assert_eq!(scopes.resolve("a"), Some(0));
+        scopes.leave();
+        assert_eq!(scopes.local("c"), (1, true));
+        assert_eq!(scopes.resolve("b"), None);
+        assert_eq!(scopes.frame_size, 2);
+    }
+}
@@ -1,43 +1,92 @@
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
 
+mod bigint;
+mod builtins;
+mod bytecode;
 mod cli;
+mod compiler;
+mod emit_c;
+mod environment;
+mod error;
+mod expander;
 mod lexer;
+mod lowering;
+mod native;
+mod number;
 mod parser;
+mod repl;
 mod runtime;
-mod types;
+mod span;
+mod value;
+mod vm;
 
-use cli::Cli;
-use lexer::Lexer;
-use parser::Parser;
+use cli::{Cli, Command};
+use error::Result;
 use runtime::Runtime;
 
 fn main() {
-    let args = Cli::new();
+    let args = match Cli::new() {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("{}\n{}", error.diagnostic(), cli::USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let result = match &args.command {
+        Command::Run(path) => run(path),
+        Command::Repl => repl::run(),
+        Command::Compile {
+            path,
+            output,
+            assembly_only,
+        } => compile(path, output.as_deref(), *assembly_only),
+        Command::EmitC { path, output } => emit_c(path, output.as_deref()),
+    };
 
-    let file = File::open(&args.path).expect("Could not open file");
+    if let Err(error) = result {
+        eprintln!("{}", error.diagnostic());
+        std::process::exit(1);
+    }
+}
+
+fn read_program(path: &Path) -> Result<Vec<parser::Argument>> {
+    let file = File::open(path)?;
 
     let mut reader = std::io::BufReader::new(file);
     let mut contents = String::new();
-    reader
-        .read_to_string(&mut contents)
-        .expect("Could not read file");
+    reader.read_to_string(&mut contents)?;
 
-    let mut lexer = Lexer::new(&contents);
+    parser::parse_source(&contents, &path.display().to_string())
+}
 
-    let mut tokens: Vec<lexer::types::LexerToken> = Vec::new();
+fn run(path: &Path) -> Result<()> {
+    let expressions = read_program(path)?;
 
-    loop {
-        let token = lexer.next_token();
-        match token.token {
-            lexer::types::Tokens::Eof => break,
-            _ => tokens.push(token),
-        }
-    }
+    let mut runtime = Runtime::new(expressions);
+    runtime.run()
+}
 
-    let mut parser = Parser::new(tokens);
-    let expressions = parser.parse();
+/// Builds a native executable, named after the source file by default.
+fn compile(path: &Path, output: Option<&Path>, assembly_only: bool) -> Result<()> {
+    let expressions = read_program(path)?;
 
-    let mut runtime = Runtime::new(expressions);
-    runtime.run();
+    let default = match assembly_only {
+        true => path.with_extension("s"),
+        false => path.with_extension(""),
+    };
+    let output = output.unwrap_or(&default);
+    native::build(&expressions, &path.display().to_string(), output, assembly_only)
+}
+
+/// Writes a self-contained C file, named after the source file by default.
+fn emit_c(path: &Path, output: Option<&Path>) -> Result<()> {
+    let expressions = read_program(path)?;
+
+    let default = path.with_extension("c");
+    let source = emit_c::generate(&expressions, &path.display().to_string())?;
+    std::fs::write(output.unwrap_or(&default), source)?;
+    Ok(())
 }
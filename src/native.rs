@@ -0,0 +1,400 @@
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use crate::compiler::expect_args;
+use crate::error::{Error, Result};
+use crate::lowering::{check_builtin, Backend, Place, Scopes};
+use crate::parser::{Argument, Expression};
+use crate::span::Span;
+use crate::value::Value;
+
+/// The support library linked into every executable; see its header for the
+/// value representation shared with the code generated here.
+pub const RUNTIME: &str = include_str!("native_runtime.c");
+
+pub const FALSE: i64 = 0x01;
+pub const TRUE: i64 = 0x09;
+pub const UNSPECIFIED: i64 = 0x11;
+pub const UNBOUND: i64 = 0x19;
+
+pub const FIXNUM_MAX: i64 = i64::MAX >> 1;
+pub const FIXNUM_MIN: i64 = i64::MIN >> 1;
+
+/// Generates GNU assembler source for x86-64 Linux. Every expression leaves
+/// its value in `%rax`; globals live in `.data` and the locals of `begin`
+/// blocks in the frame of `scheme_main`.
+struct Codegen {
+    text: String,
+    /// Out-of-line error paths, emitted after the main body.
+    stubs: String,
+    data: String,
+    rodata: String,
+    scopes: Scopes,
+    /// Values currently pushed on the machine stack, for call alignment.
+    depth: usize,
+    labels: usize,
+}
+
+/// Renders `bytes` for an `.ascii` directive.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => write!(escaped, "\\{}", byte as char).unwrap(),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => write!(escaped, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    escaped
+}
+
+impl Codegen {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            stubs: String::new(),
+            data: String::new(),
+            rodata: String::new(),
+            scopes: Scopes::default(),
+            depth: 0,
+            labels: 0,
+        }
+    }
+
+    fn emit(&mut self, instruction: &str) {
+        self.text.push_str("    ");
+        self.text.push_str(instruction);
+        self.text.push('\n');
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        writeln!(self.text, "{}:", label).unwrap();
+    }
+
+    fn push(&mut self) {
+        self.emit("push %rax");
+        self.depth += 1;
+    }
+
+    fn pop(&mut self, register: &str) {
+        self.emit(&format!("pop {}", register));
+        self.depth -= 1;
+    }
+
+    fn immediate(&mut self, value: i64) {
+        self.emit(&format!("movabs ${}, %rax", value));
+    }
+
+    /// A read-only NUL-terminated string, for names and source locations.
+    fn cstring(&mut self, text: &str) -> String {
+        let label = self.label();
+        writeln!(self.rodata, "{}:\n    .asciz \"{}\"", label, escape(text.as_bytes())).unwrap();
+        label
+    }
+
+    /// Records `span` as the location runtime errors are reported at.
+    fn locate(&mut self, span: &Span) {
+        let location = self.cstring(&span.to_string());
+        self.emit(&format!("lea {}(%rip), %r11", location));
+        self.emit("mov %r11, scm_location(%rip)");
+    }
+
+    /// Calls a runtime function with the stack aligned to 16 bytes.
+    fn call(&mut self, function: &str, span: &Span) {
+        self.locate(span);
+        let misaligned = self.depth % 2 == 1;
+        if misaligned {
+            self.emit("sub $8, %rsp");
+        }
+        self.emit(&format!("call {}", function));
+        if misaligned {
+            self.emit("add $8, %rsp");
+        }
+    }
+
+    fn address(place: Place) -> String {
+        match place {
+            Place::Local(slot) => format!("{}(%rbp)", -8 * (slot as i64 + 1)),
+            Place::Global(index) => format!("global_{}(%rip)", index),
+        }
+    }
+
+    /// Jumps to an error stub when `%rax` holds an unbound global.
+    fn check_bound(&mut self, name: &str, span: &Span) {
+        let stub = self.label();
+        let location = self.cstring(&span.to_string());
+        let name = self.cstring(name);
+        self.emit(&format!("cmp ${}, %rax", UNBOUND));
+        self.emit(&format!("je {}", stub));
+        writeln!(
+            self.stubs,
+            "{}:\n    lea {}(%rip), %r11\n    mov %r11, scm_location(%rip)\n    lea {}(%rip), %rdi\n    and $-16, %rsp\n    call scm_unbound",
+            stub, location, name
+        )
+        .unwrap();
+    }
+
+    fn finish(self, source: &str) -> String {
+        let frame = (self.scopes.frame_size * 8).div_ceil(16) * 16;
+        format!(
+            "# generated by scheme-compilator from {source}\n\
+             \x20   .text\n\
+             \x20   .globl scheme_main\n\
+             scheme_main:\n\
+             \x20   push %rbp\n\
+             \x20   mov %rsp, %rbp\n\
+             \x20   sub ${frame}, %rsp\n\
+             {text}\
+             \x20   leave\n\
+             \x20   ret\n\
+             {stubs}\
+             \x20   .data\n\
+             \x20   .balign 8\n\
+             {data}\
+             \x20   .section .rodata\n\
+             {rodata}\
+             \x20   .section .note.GNU-stack,\"\",@progbits\n",
+            source = source,
+            frame = frame,
+            text = self.text,
+            stubs = self.stubs,
+            data = self.data,
+            rodata = self.rodata,
+        )
+    }
+}
+
+impl Backend for Codegen {
+    type Value = ();
+
+    fn scopes(&mut self) -> &mut Scopes {
+        &mut self.scopes
+    }
+
+    fn allocate_global(&mut self, index: usize, name: &str) {
+        writeln!(
+            self.data,
+            "global_{}:  # {}\n    .quad {}",
+            index,
+            escape(name.as_bytes()),
+            UNBOUND
+        )
+        .unwrap();
+    }
+
+    fn initialize_local(&mut self, slot: usize) {
+        self.emit(&format!("movq ${}, {}", UNSPECIFIED, Self::address(Place::Local(slot))));
+    }
+
+    fn unspecified(&mut self) {
+        self.immediate(UNSPECIFIED);
+    }
+
+    fn discard(&mut self, _: ()) {}
+
+    fn constant(&mut self, value: &Value) {
+        match value {
+            Value::Int(n) => self.immediate(n << 1),
+            Value::Bool(true) => self.immediate(TRUE),
+            Value::Bool(false) => self.immediate(FALSE),
+            Value::String(string) => {
+                let label = self.label();
+                writeln!(
+                    self.rodata,
+                    "    .balign 8\n{}:\n    .quad 1, {}\n    .ascii \"{}\"\n    .byte 0",
+                    label,
+                    string.len(),
+                    escape(string.as_bytes())
+                )
+                .unwrap();
+                self.emit(&format!("lea {}+3(%rip), %rax", label));
+            }
+            _ => unreachable!("rejected by check_literal"),
+        }
+    }
+
+    fn load(&mut self, place: Place, name: &str, span: &Span) {
+        self.emit(&format!("mov {}, %rax", Self::address(place)));
+        if let Place::Global(_) = place {
+            self.check_bound(name, span);
+        }
+    }
+
+    fn store(&mut self, place: Place, _: ()) {
+        self.emit(&format!("mov %rax, {}", Self::address(place)));
+    }
+
+    fn update(&mut self, place: Place, _: (), name: &str, span: &Span) {
+        if let Place::Local(_) = place {
+            return self.store(place, ());
+        }
+        let address = Self::address(place);
+        self.emit("mov %rax, %rcx");
+        self.emit(&format!("mov {}, %rax", address));
+        self.check_bound(name, span);
+        self.emit(&format!("mov %rcx, {}", address));
+    }
+
+    fn while_statement(&mut self, expr: &Expression) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("while", args, 1, None)?;
+        let (start, end) = (self.label(), self.label());
+        self.place(&start);
+        self.expression(&args[0])?;
+        self.emit(&format!("cmp ${}, %rax", FALSE));
+        self.emit(&format!("je {}", end));
+        for arg in &args[1..] {
+            self.expression(arg)?;
+        }
+        self.emit(&format!("jmp {}", start));
+        self.place(&end);
+        self.immediate(UNSPECIFIED);
+        Ok(())
+    }
+
+    fn if_statement(&mut self, expr: &Expression) -> Result<()> {
+        let args = &expr.arguments;
+        expect_args("if", args, 2, Some(3))?;
+        let (otherwise, end) = (self.label(), self.label());
+        self.expression(&args[0])?;
+        self.emit(&format!("cmp ${}, %rax", FALSE));
+        self.emit(&format!("je {}", otherwise));
+        self.expression(&args[1])?;
+        self.emit(&format!("jmp {}", end));
+        self.place(&otherwise);
+        match args.get(2) {
+            Some(arg) => self.expression(arg)?,
+            None => self.immediate(UNSPECIFIED),
+        }
+        self.place(&end);
+        Ok(())
+    }
+
+    fn builtin_call(&mut self, name: &str, expr: &Expression) -> Result<()> {
+        let function = check_builtin(name, expr)?;
+        let args = &expr.arguments;
+
+        match name {
+            "display" => {
+                for arg in args {
+                    self.expression(arg)?;
+                    self.push();
+                }
+                self.emit("mov %rsp, %rsi");
+                self.emit(&format!("mov ${}, %rdi", args.len()));
+                self.call(function, &expr.span);
+                self.emit(&format!("add ${}, %rsp", 8 * args.len()));
+                self.depth -= args.len();
+            }
+            "newline" | "read" | "read-line" => self.call(function, &expr.span),
+            // Binary operators fold left; one operand is combined with the identity.
+            _ => match args.split_first() {
+                None => self.immediate(if name == "*" { 1 << 1 } else { 0 }),
+                Some((first, [])) => {
+                    self.expression(first)?;
+                    self.emit("mov %rax, %rsi");
+                    let identity = if name == "*" || name == "/" { 1 << 1 } else { 0 };
+                    self.emit(&format!("mov ${}, %rdi", identity));
+                    self.call(function, &expr.span);
+                }
+                Some((first, rest)) => {
+                    self.expression(first)?;
+                    for arg in rest {
+                        self.push();
+                        self.expression(arg)?;
+                        self.emit("mov %rax, %rsi");
+                        self.pop("%rdi");
+                        self.call(function, &expr.span);
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Translates a whole program to assembly for `scheme_main`.
+pub fn generate(program: &[Argument], source: &str) -> Result<String> {
+    let mut codegen = Codegen::new();
+    for arg in program {
+        codegen.toplevel(arg)?;
+    }
+    Ok(codegen.finish(source))
+}
+
+fn run_tool(program: &str, args: &[&Path]) -> Result<()> {
+    let status = Command::new(program).args(args).status().map_err(|error| {
+        Error::Io(io::Error::new(error.kind(), format!("{}: {}", program, error)))
+    })?;
+    if !status.success() {
+        return Err(Error::Io(io::Error::other(format!("{} failed ({})", program, status))));
+    }
+    Ok(())
+}
+
+/// Compiles `program` to a standalone executable at `output`, using the
+/// system assembler and C compiler. With `assembly_only`, writes the
+/// generated assembly to `output` instead.
+pub fn build(program: &[Argument], source: &str, output: &Path, assembly_only: bool) -> Result<()> {
+    let assembly = generate(program, source)?;
+    if assembly_only {
+        fs::write(output, assembly)?;
+        return Ok(());
+    }
+
+    let dir = std::env::temp_dir().join(format!("scheme-compilator-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let result = (|| {
+        let (program_s, program_o) = (dir.join("program.s"), dir.join("program.o"));
+        let (runtime_c, runtime_o) = (dir.join("runtime.c"), dir.join("runtime.o"));
+        fs::write(&program_s, assembly)?;
+        fs::write(&runtime_c, RUNTIME)?;
+        run_tool("as", &[&program_s, Path::new("-o"), &program_o])?;
+        run_tool("cc", &[Path::new("-O2"), Path::new("-c"), &runtime_c, Path::new("-o"), &runtime_o])?;
+        run_tool("cc", &[&program_o, &runtime_o, Path::new("-o"), output])
+    })();
+    fs::remove_dir_all(&dir)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn programs_compile_to_working_executables() {
+        let source = "(define n 10) (define a 0) (define b 1)
+                      (while (> n 0)
+                        (begin
+                          (define next (+ a b))
+                          (set! a b)
+                          (set! b next)
+                          (set! n (- n 1))))
+                      (display \"fib \" a \"\\n\")
+                      (display (if (< 1 2) \"yes\" \"no\") \"\\n\")";
+        let program = parser::parse_source(source, "<test>").unwrap();
+        let dir = std::env::temp_dir().join(format!("scheme-native-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let executable = dir.join("program");
+        build(&program, "<test>", &executable, false).unwrap();
+        let output = Command::new(&executable).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "fib 55\nyes\n");
+    }
+
+    #[test]
+    fn constructs_outside_the_subset_are_compile_errors() {
+        let program = parser::parse_source("(display 1.5)", "<test>").unwrap();
+        let error = generate(&program, "<test>").unwrap_err();
+        assert_eq!(error.to_string(), "compiled programs do not support floating-point literals at <test>:1:10");
+    }
+}
@@ -0,0 +1,265 @@
+/* Runtime support for programs built by `scheme-compilator compile` and
+ * `scheme-compilator emit-c`. Plain C99, so any C compiler accepts it.
+ *
+ * Values are tagged 64-bit words:
+ *   fixnum     n << 1                (low bit 0)
+ *   immediate  ...001                (#f, #t, unspecified, unbound)
+ *   object     address | 3           (8-byte aligned heap or static object)
+ *
+ * Every object starts with a type word. Generated code calls the scm_*
+ * functions below and defines scheme_main. */
+
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef int64_t value;
+
+#define FALSE 0x01
+#define TRUE 0x09
+#define UNSPECIFIED 0x11
+#define UNBOUND 0x19
+
+#define TYPE_STRING 1
+
+#define IS_FIXNUM(v) (((v) & 1) == 0)
+#define FIXNUM(n) ((value)((uint64_t)(n) << 1))
+#define UNFIX(v) ((v) >> 1)
+#define OBJECT(v) ((int64_t *)(intptr_t)((v) - 3))
+#define IS_STRING(v) (((v) & 7) == 3 && OBJECT(v)[0] == TYPE_STRING)
+
+struct string {
+    int64_t type;
+    int64_t length;
+    char data[];
+};
+
+#define STRING(v) ((struct string *)OBJECT(v))
+
+#define FIXNUM_MAX (INT64_MAX >> 1)
+#define FIXNUM_MIN (INT64_MIN >> 1)
+
+/* The source location of the call in progress, set by the generated code. */
+const char *scm_location = "?";
+
+extern void scheme_main(void);
+
+#ifdef __GNUC__
+static void fail(const char *format, ...) __attribute__((noreturn, format(printf, 1, 2)));
+#endif
+
+static void fail(const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    fputs("error: ", stderr);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fprintf(stderr, " at %s\n", scm_location);
+    exit(1);
+}
+
+static void *allocate(size_t size) {
+    void *memory = malloc(size);
+    if (memory == NULL) {
+        fail("out of memory");
+    }
+    return memory;
+}
+
+static value make_string(const char *data, size_t length) {
+    struct string *string = allocate(sizeof(struct string) + length + 1);
+    string->type = TYPE_STRING;
+    string->length = (int64_t)length;
+    memcpy(string->data, data, length);
+    string->data[length] = '\0';
+    return (value)(intptr_t)string | 3;
+}
+
+static const char *type_name(value v) {
+    if (IS_FIXNUM(v)) {
+        return "integer";
+    }
+    if (v == TRUE || v == FALSE) {
+        return "boolean";
+    }
+    if (IS_STRING(v)) {
+        return "string";
+    }
+    return "unspecified";
+}
+
+static int64_t number(const char *procedure, value v) {
+    if (!IS_FIXNUM(v)) {
+        fail("%s: expected a number, got %s", procedure, type_name(v));
+    }
+    return UNFIX(v);
+}
+
+static void overflow(const char *procedure) {
+    fail("%s: integer overflow (compiled programs do not support big integers)", procedure);
+}
+
+static value fixnum(const char *procedure, int64_t n) {
+    if (n > FIXNUM_MAX || n < FIXNUM_MIN) {
+        overflow(procedure);
+    }
+    return FIXNUM(n);
+}
+
+/* Fixnums have 63 bits, so sums and differences cannot overflow an int64_t. */
+value scm_add(value a, value b) { return fixnum("+", number("+", a) + number("+", b)); }
+value scm_sub(value a, value b) { return fixnum("-", number("-", a) - number("-", b)); }
+
+value scm_mul(value a, value b) {
+    int64_t x = number("*", a), y = number("*", b);
+    int64_t limit = (x < 0) != (y < 0) ? -FIXNUM_MIN : FIXNUM_MAX;
+    if (x != 0 && (y < 0 ? -y : y) > limit / (x < 0 ? -x : x)) {
+        overflow("*");
+    }
+    return FIXNUM(x * y);
+}
+
+value scm_div(value a, value b) {
+    int64_t x = number("/", a), y = number("/", b);
+    if (y == 0) {
+        fail("/: division by zero");
+    }
+    if (x % y != 0) {
+        fail("/: compiled programs do not support rational results");
+    }
+    return fixnum("/", x / y);
+}
+
+value scm_rem(value a, value b) {
+    int64_t x = number("%", a), y = number("%", b);
+    if (y == 0) {
+        fail("%%: division by zero");
+    }
+    return FIXNUM(x % y);
+}
+
+value scm_lt(value a, value b) { return number("<", a) < number("<", b) ? TRUE : FALSE; }
+value scm_le(value a, value b) { return number("<=", a) <= number("<=", b) ? TRUE : FALSE; }
+value scm_gt(value a, value b) { return number(">", a) > number(">", b) ? TRUE : FALSE; }
+value scm_ge(value a, value b) { return number(">=", a) >= number(">=", b) ? TRUE : FALSE; }
+
+/* Strings compare by content and numbers by value; anything else is unequal. */
+value scm_eq(value a, value b) {
+    if (IS_FIXNUM(a) && IS_FIXNUM(b)) {
+        return a == b ? TRUE : FALSE;
+    }
+    if (IS_STRING(a) && IS_STRING(b)) {
+        struct string *x = STRING(a), *y = STRING(b);
+        return x->length == y->length && memcmp(x->data, y->data, x->length) == 0 ? TRUE : FALSE;
+    }
+    return FALSE;
+}
+
+value scm_ne(value a, value b) { return scm_eq(a, b) == TRUE ? FALSE : TRUE; }
+
+struct buffer {
+    char *data;
+    size_t length, capacity;
+};
+
+static void append(struct buffer *buffer, const char *data, size_t length) {
+    if (buffer->length + length + 1 > buffer->capacity) {
+        buffer->capacity = (buffer->length + length + 1) * 2;
+        buffer->data = realloc(buffer->data, buffer->capacity);
+        if (buffer->data == NULL) {
+            fail("out of memory");
+        }
+    }
+    memcpy(buffer->data + buffer->length, data, length);
+    buffer->length += length;
+    buffer->data[buffer->length] = '\0';
+}
+
+/* Prints the arguments one after another, strings verbatim. `args` holds the
+ * arguments last-first, the order the native backend pushes them. */
+value scm_display(int64_t argc, value *args) {
+    for (int64_t i = argc - 1; i >= 0; i--) {
+        value v = args[i];
+        if (IS_FIXNUM(v)) {
+            printf("%lld", (long long)UNFIX(v));
+        } else if (IS_STRING(v)) {
+            fwrite(STRING(v)->data, 1, (size_t)STRING(v)->length, stdout);
+        } else if (v == TRUE) {
+            fputs("#t", stdout);
+        } else if (v == FALSE) {
+            fputs("#f", stdout);
+        } else {
+            fputs("#<unspecified>", stdout);
+        }
+    }
+    return UNSPECIFIED;
+}
+
+value scm_newline(void) {
+    putchar('\n');
+    return UNSPECIFIED;
+}
+
+/* Reads one line without its line terminator; end of input reads as "". */
+static struct buffer read_line(void) {
+    struct buffer line = {0};
+    char chunk[256];
+    append(&line, "", 0);
+    fflush(stdout);
+    while (fgets(chunk, sizeof chunk, stdin) != NULL) {
+        append(&line, chunk, strlen(chunk));
+        if (line.data[line.length - 1] == '\n') {
+            break;
+        }
+    }
+    if (line.length > 0 && line.data[line.length - 1] == '\n') {
+        line.data[--line.length] = '\0';
+        if (line.length > 0 && line.data[line.length - 1] == '\r') {
+            line.data[--line.length] = '\0';
+        }
+    }
+    return line;
+}
+
+value scm_read_line(void) {
+    struct buffer line = read_line();
+    value string = make_string(line.data, line.length);
+    free(line.data);
+    return string;
+}
+
+value scm_read(void) {
+    struct buffer line = read_line();
+    const char *start = line.data;
+    while (*start == ' ' || *start == '\t') {
+        start++;
+    }
+    size_t length = strlen(start);
+    while (length > 0 && (start[length - 1] == ' ' || start[length - 1] == '\t')) {
+        length--;
+    }
+
+    char *digits = allocate(length + 1), *end;
+    memcpy(digits, start, length);
+    digits[length] = '\0';
+    long long n = strtoll(digits, &end, 10);
+    if (length == 0 || *end != '\0' || n > FIXNUM_MAX || n < FIXNUM_MIN) {
+        fail("read: expected an integer, got \"%s\"", line.data);
+    }
+    free(digits);
+    free(line.data);
+    return FIXNUM(n);
+}
+
+void scm_unbound(const char *name) {
+    fail("unbound variable '%s'", name);
+}
+
+int main(void) {
+    scheme_main();
+    fflush(stdout);
+    return 0;
+}
@@ -0,0 +1,412 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::error::{Error, Result};
+use crate::value::Value;
+
+/// Exact non-integer ratio, kept in lowest terms with a denominator above one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rational {
+    pub numerator: BigInt,
+    pub denominator: BigInt,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Integer,
+    Rational,
+    Float,
+}
+
+fn level(name: &str, value: &Value) -> Result<Level> {
+    match value {
+        Value::Int(_) | Value::BigInt(_) => Ok(Level::Integer),
+        Value::Rational(_) => Ok(Level::Rational),
+        Value::Float(_) => Ok(Level::Float),
+        _ => Err(Error::type_error(name, "a number", value.type_name())),
+    }
+}
+
+pub fn is_number(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Int(_) | Value::BigInt(_) | Value::Rational(_) | Value::Float(_)
+    )
+}
+
+/// Demotes to a fixnum whenever the value fits in 64 bits.
+pub fn integer(n: BigInt) -> Value {
+    match n.to_i64() {
+        Some(n) => Value::Int(n),
+        None => Value::BigInt(Rc::new(n)),
+    }
+}
+
+/// Builds `numerator / denominator` in lowest terms, collapsing to an integer
+/// when the denominator divides out. The denominator must be non-zero.
+pub fn rational(numerator: BigInt, denominator: BigInt) -> Value {
+    let (mut numerator, mut denominator) = (numerator, denominator);
+    if denominator.is_negative() {
+        numerator = numerator.neg();
+        denominator = denominator.neg();
+    }
+    let gcd = numerator.gcd(&denominator);
+    if !gcd.is_one() && !gcd.is_zero() {
+        numerator = numerator.div_rem(&gcd).0;
+        denominator = denominator.div_rem(&gcd).0;
+    }
+    if denominator.is_one() {
+        return integer(numerator);
+    }
+    Value::Rational(Rc::new(Rational {
+        numerator,
+        denominator,
+    }))
+}
+
+fn to_bigint(value: &Value) -> BigInt {
+    match value {
+        Value::Int(n) => BigInt::from_i64(*n),
+        Value::BigInt(n) => n.as_ref().clone(),
+        _ => unreachable!(),
+    }
+}
+
+fn to_ratio(value: &Value) -> (BigInt, BigInt) {
+    match value {
+        Value::Rational(r) => (r.numerator.clone(), r.denominator.clone()),
+        _ => (to_bigint(value), BigInt::from_i64(1)),
+    }
+}
+
+/// The float nearest to `numerator / denominator`. The quotient is computed
+/// exactly to 63 bits, with a sticky bit for any remainder, so it is rounded
+/// once however large both sides are.
+fn ratio_to_f64(numerator: &BigInt, denominator: &BigInt) -> f64 {
+    if numerator.is_zero() {
+        return 0.0;
+    }
+    let shift = 62 - (numerator.bit_length() as i64 - denominator.bit_length() as i64);
+    let (scaled, divisor) = match shift >= 0 {
+        true => (numerator.abs().shl(shift as u32), denominator.abs()),
+        false => (numerator.abs(), denominator.abs().shl(-shift as u32)),
+    };
+    let (quotient, remainder) = scaled.div_rem(&divisor);
+    let quotient = quotient.to_i64().expect("the quotient fits in 63 bits");
+    let sticky = !remainder.is_zero() as i64;
+    let mut result = (quotient | sticky) as f64;
+    // Scale in steps so that neither factor overflows or underflows on the way.
+    let mut exponent = -shift;
+    while exponent != 0 {
+        let step = exponent.clamp(-1000, 1000);
+        result *= 2f64.powi(step as i32);
+        exponent -= step;
+    }
+    match numerator.is_negative() != denominator.is_negative() {
+        true => -result,
+        false => result,
+    }
+}
+
+pub fn to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::BigInt(n) => Some(ratio_to_f64(n, &BigInt::from_i64(1))),
+        Value::Rational(r) => Some(ratio_to_f64(&r.numerator, &r.denominator)),
+        Value::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn inexact(name: &str, value: &Value) -> Result<f64> {
+    match to_f64(value) {
+        Some(n) => Ok(n),
+        None => Err(Error::type_error(name, "a number", value.type_name())),
+    }
+}
+
+type RatioOp = fn((BigInt, BigInt), (BigInt, BigInt)) -> Result<Value>;
+
+fn arithmetic(
+    name: &str,
+    a: &Value,
+    b: &Value,
+    fixnum: fn(i64, i64) -> Option<i64>,
+    bignum: fn(&BigInt, &BigInt) -> BigInt,
+    ratio: RatioOp,
+    float: fn(f64, f64) -> f64,
+) -> Result<Value> {
+    if let (Value::Int(x), Value::Int(y)) = (a, b) {
+        if let Some(n) = fixnum(*x, *y) {
+            return Ok(Value::Int(n));
+        }
+    }
+    let level = std::cmp::max(level(name, a)?, level(name, b)?);
+    match level {
+        Level::Integer => Ok(integer(bignum(&to_bigint(a), &to_bigint(b)))),
+        Level::Rational => ratio(to_ratio(a), to_ratio(b)),
+        Level::Float => Ok(Value::Float(float(inexact(name, a)?, inexact(name, b)?))),
+    }
+}
+
+pub fn add(a: &Value, b: &Value) -> Result<Value> {
+    arithmetic(
+        "+",
+        a,
+        b,
+        i64::checked_add,
+        BigInt::add,
+        |(n1, d1), (n2, d2)| Ok(rational(n1.mul(&d2).add(&n2.mul(&d1)), d1.mul(&d2))),
+        |x, y| x + y,
+    )
+}
+
+pub fn sub(a: &Value, b: &Value) -> Result<Value> {
+    arithmetic(
+        "-",
+        a,
+        b,
+        i64::checked_sub,
+        BigInt::sub,
+        |(n1, d1), (n2, d2)| Ok(rational(n1.mul(&d2).sub(&n2.mul(&d1)), d1.mul(&d2))),
+        |x, y| x - y,
+    )
+}
+
+pub fn mul(a: &Value, b: &Value) -> Result<Value> {
+    arithmetic(
+        "*",
+        a,
+        b,
+        i64::checked_mul,
+        BigInt::mul,
+        |(n1, d1), (n2, d2)| Ok(rational(n1.mul(&n2), d1.mul(&d2))),
+        |x, y| x * y,
+    )
+}
+
+/// Exact operands produce an exact quotient, which may be a ratio.
+pub fn div(a: &Value, b: &Value) -> Result<Value> {
+    if level("/", a)? < Level::Float && level("/", b)? < Level::Float {
+        let (n1, d1) = to_ratio(a);
+        let (n2, d2) = to_ratio(b);
+        if n2.is_zero() {
+            return Err(Error::DivisionByZero("/".to_string()));
+        }
+        return Ok(rational(n1.mul(&d2), d1.mul(&n2)));
+    }
+    Ok(Value::Float(inexact("/", a)? / inexact("/", b)?))
+}
+
+/// Truncating remainder: `a - b * truncate(a / b)`.
+pub fn rem(a: &Value, b: &Value) -> Result<Value> {
+    if level("%", b)? < Level::Float && to_ratio(b).0.is_zero() {
+        return Err(Error::DivisionByZero("%".to_string()));
+    }
+    arithmetic(
+        "%",
+        a,
+        b,
+        i64::checked_rem,
+        |x, y| x.div_rem(y).1,
+        |(n1, d1), (n2, d2)| {
+            let quotient = n1.mul(&d2).div_rem(&d1.mul(&n2)).0;
+            Ok(rational(
+                n1.mul(&d2).sub(&quotient.mul(&n2).mul(&d1)),
+                d1.mul(&d2),
+            ))
+        },
+        |x, y| x % y,
+    )
+}
+
+pub fn negate(a: &Value) -> Result<Value> {
+    sub(&Value::Int(0), a)
+}
+
+/// Returns `None` when either side is NaN, so every ordered comparison fails.
+pub fn compare(name: &str, a: &Value, b: &Value) -> Result<Option<Ordering>> {
+    if let (Value::Int(x), Value::Int(y)) = (a, b) {
+        return Ok(Some(x.cmp(y)));
+    }
+    let level = std::cmp::max(level(name, a)?, level(name, b)?);
+    Ok(match level {
+        Level::Integer => Some(to_bigint(a).cmp(&to_bigint(b))),
+        Level::Rational => {
+            let (n1, d1) = to_ratio(a);
+            let (n2, d2) = to_ratio(b);
+            Some(n1.mul(&d2).cmp(&n2.mul(&d1)))
+        }
+        Level::Float => match (a, b) {
+            (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+            (Value::Float(x), _) => compare_with_float(name, b, *x)?.map(Ordering::reverse),
+            _ => compare_with_float(name, a, inexact(name, b)?)?,
+        },
+    })
+}
+
+/// Compares an exact number with a float by converting the float to the
+/// exact number it stands for, not by rounding the exact one to a float.
+fn compare_with_float(name: &str, exact: &Value, float: f64) -> Result<Option<Ordering>> {
+    if float.is_nan() {
+        return Ok(None);
+    }
+    if float.is_infinite() {
+        return Ok(Some(match float > 0.0 {
+            true => Ordering::Less,
+            false => Ordering::Greater,
+        }));
+    }
+    compare(name, exact, &inexact_to_exact(&Value::Float(float))?)
+}
+
+pub fn exact_to_inexact(a: &Value) -> Result<Value> {
+    Ok(Value::Float(inexact("exact->inexact", a)?))
+}
+
+pub fn inexact_to_exact(a: &Value) -> Result<Value> {
+    match level("inexact->exact", a)? {
+        Level::Float => {
+            let n = inexact("inexact->exact", a)?;
+            if !n.is_finite() {
+                return Err(Error::type_error(
+                    "inexact->exact",
+                    "a finite number",
+                    &format_float(n),
+                ));
+            }
+            let bits = n.to_bits();
+            let negative = bits >> 63 == 1;
+            let exponent = ((bits >> 52) & 0x7ff) as i64;
+            let fraction = bits & 0x000f_ffff_ffff_ffff;
+            let (mantissa, exponent) = if exponent == 0 {
+                (fraction, -1074)
+            } else {
+                (fraction | (1 << 52), exponent - 1075)
+            };
+            let mut mantissa = BigInt::from_u64(mantissa);
+            if negative {
+                mantissa = mantissa.neg();
+            }
+            if exponent >= 0 {
+                Ok(integer(mantissa.shl(exponent as u32)))
+            } else {
+                Ok(rational(mantissa, BigInt::from_i64(1).shl(-exponent as u32)))
+            }
+        }
+        _ => Ok(a.clone()),
+    }
+}
+
+pub fn numerator(a: &Value) -> Result<Value> {
+    match level("numerator", a)? {
+        Level::Float => exact_to_inexact(&numerator(&inexact_to_exact(a)?)?),
+        _ => Ok(integer(to_ratio(a).0)),
+    }
+}
+
+pub fn denominator(a: &Value) -> Result<Value> {
+    match level("denominator", a)? {
+        Level::Float => exact_to_inexact(&denominator(&inexact_to_exact(a)?)?),
+        _ => Ok(integer(to_ratio(a).1)),
+    }
+}
+
+/// Parses integer, ratio (`1/3`) and decimal literals.
+pub fn parse(text: &str) -> Option<Value> {
+    if let Some((numerator, denominator)) = text.split_once('/') {
+        let numerator = BigInt::parse(numerator)?;
+        let denominator = BigInt::parse(denominator)?;
+        if denominator.is_negative() || denominator.is_zero() || text.contains("/+") {
+            return None;
+        }
+        return Some(rational(numerator, denominator));
+    }
+    if let Ok(n) = text.parse::<i64>() {
+        return Some(Value::Int(n));
+    }
+    if let Some(n) = BigInt::parse(text) {
+        return Some(integer(n));
+    }
+    if text.bytes().any(|b| b.is_ascii_digit()) {
+        if let Ok(n) = text.parse::<f64>() {
+            return Some(Value::Float(n));
+        }
+    }
+    None
+}
+
+pub fn format_float(n: f64) -> String {
+    if n.is_finite() && n.fract() == 0.0 {
+        format!("{:.1}", n)
+    } else if n.is_nan() {
+        "+nan.0".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "+inf.0" } else { "-inf.0" }.to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::eval;
+
+    #[test]
+    fn integer_arithmetic_stays_exact() {
+        assert_eq!(eval("(+ 1 2)").unwrap(), "3");
+        assert_eq!(eval("(* 6 7)").unwrap(), "42");
+        assert_eq!(eval("(% 17 5)").unwrap(), "2");
+        assert_eq!(eval("(+ 9007199254740992 1)").unwrap(), "9007199254740993");
+    }
+
+    #[test]
+    fn an_inexact_operand_makes_the_result_a_float() {
+        assert_eq!(eval("(+ 1 2.5)").unwrap(), "3.5");
+        assert_eq!(eval("(- 10 4.0)").unwrap(), "6.0");
+    }
+
+    #[test]
+    fn fixnum_overflow_is_detected() {
+        assert_eq!(eval("(+ 9223372036854775807 1)").unwrap(), "9223372036854775808");
+        assert_eq!(eval("(- -9223372036854775808 1)").unwrap(), "-9223372036854775809");
+    }
+
+    #[test]
+    fn integers_grow_into_bignums_and_division_makes_ratios() {
+        assert_eq!(eval("(* 99999999999 99999999999 99999999999)").unwrap(), "999999999970000000000299999999999");
+        assert_eq!(eval("(/ 1 3)").unwrap(), "1/3");
+        assert_eq!(eval("(+ 1/3 2/3)").unwrap(), "1");
+        assert_eq!(eval("(numerator 6/4)").unwrap(), "3");
+        assert_eq!(eval("(inexact->exact 0.5)").unwrap(), "1/2");
+    }
+
+    /// `(pow b n)`, since there is no `expt` to build huge numbers with.
+    const POW: &str = "(define (pow b n) (if (= n 0) 1 (* b (pow b (- n 1)))))";
+
+    #[test]
+    fn huge_ratios_convert_to_the_nearest_float() {
+        let eval = |source: &str| eval(&format!("{} {}", POW, source)).unwrap();
+        assert_eq!(eval("(exact->inexact (/ (+ (pow 10 400) 1) (pow 10 399)))"), "10.0");
+        assert_eq!(eval("(exact->inexact (/ 1 (pow 10 400)))"), "0.0");
+        assert_eq!(eval("(exact->inexact (- (pow 10 400)))"), "-inf.0");
+        assert_eq!(eval("(exact->inexact 1/3)"), (1.0f64 / 3.0).to_string());
+        assert_eq!(eval("(exact->inexact (pow 2 1023))"), format!("{:.1}", 2f64.powi(1023)));
+    }
+
+    #[test]
+    fn exact_and_inexact_numbers_compare_exactly() {
+        let eval = |source: &str| eval(&format!("{} {}", POW, source)).unwrap();
+        assert_eq!(eval("(= 1/3 (exact->inexact 1/3))"), "#f");
+        assert_eq!(eval("(< 1/3 (exact->inexact 1/3))"), "#f");
+        assert_eq!(eval("(= 1/2 0.5)"), "#t");
+        assert_eq!(eval("(= 9007199254740993 9007199254740992.0)"), "#f");
+        assert_eq!(eval("(< (pow 10 400) (/ 1.0 0))"), "#t");
+    }
+
+    #[test]
+    fn dividing_by_exact_zero_is_an_error() {
+        assert!(eval("(% 1 0)").unwrap_err().to_string().starts_with("%: division by zero"));
+    }
+}
@@ -1,7 +1,12 @@
+use crate::error::{Error, Result};
 use crate::lexer::types::LexerToken;
+use crate::lexer::Lexer;
 use crate::lexer::types::Punctuations;
 use crate::lexer::types::Tokens;
 use crate::lexer::types::Types;
+use crate::number;
+use crate::span::Span;
+use crate::value::Value;
 
 #[derive(Clone)]
 pub struct Parser {
@@ -16,18 +21,96 @@ pub enum Argument {
 }
 #[derive(Debug, Clone)]
 pub struct Expression {
-    pub function: String,
+    pub function: Box<Argument>,
     pub arguments: Vec<Argument>,
+    /// The datum after a `.` in a dotted form such as `(a b . rest)`.
+    pub rest: Option<Box<Argument>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
-
 pub struct LiteralVariable {
-    pub var_type: Types,
-    pub value: String,
+    pub value: Value,
+    pub span: Span,
+}
+
+impl Argument {
+    pub fn span(&self) -> &Span {
+        match self {
+            Argument::Expression(expr) => &expr.span,
+            Argument::LiteralVariable(literal) => &literal.span,
+        }
+    }
+
+    /// The form as literal data, the way `quote` sees it.
+    pub fn to_datum(&self) -> Value {
+        match self {
+            Argument::Expression(expr) => {
+                let tail = match &expr.rest {
+                    Some(rest) => rest.to_datum(),
+                    None => Value::Nil,
+                };
+                let items = std::iter::once(expr.function.as_ref()).chain(&expr.arguments);
+                Value::list_with_tail(items.map(Argument::to_datum).collect(), tail)
+            }
+            Argument::LiteralVariable(literal) => literal.value.clone(),
+        }
+    }
+
+    /// Literal data as a form, the inverse of `to_datum`. Every part of the
+    /// result is given `span`, since data carry no source positions.
+    pub fn from_datum(value: &Value, span: &Span) -> Argument {
+        let Value::Pair(pair) = value else {
+            return Argument::LiteralVariable(LiteralVariable {
+                value: value.clone(),
+                span: span.clone(),
+            });
+        };
+        let function = Box::new(Argument::from_datum(&pair.borrow().car, span));
+        let mut arguments = Vec::new();
+        let mut current = pair.borrow().cdr.clone();
+        loop {
+            current = match current {
+                Value::Pair(pair) => {
+                    let pair = pair.borrow();
+                    arguments.push(Argument::from_datum(&pair.car, span));
+                    pair.cdr.clone()
+                }
+                _ => break,
+            }
+        }
+        let rest = match current {
+            Value::Nil => None,
+            tail => Some(Box::new(Argument::from_datum(&tail, span))),
+        };
+        Argument::Expression(Expression {
+            function,
+            arguments,
+            rest,
+            span: span.clone(),
+        })
+    }
+}
+
+/// Lexes and parses a whole program.
+pub fn parse_source(text: &str, name: &str) -> Result<Vec<Argument>> {
+    let mut lexer = Lexer::new(text, name);
+    let mut tokens: Vec<LexerToken> = Vec::new();
+
+    loop {
+        let token = lexer.next_token()?;
+        let eof = matches!(token.token, Tokens::Eof);
+        tokens.push(token);
+        if eof {
+            break;
+        }
+    }
+
+    Parser::new(tokens).parse()
 }
 
 impl Parser {
+    /// `tokens` must end with the lexer's `Eof` token.
     pub fn new(tokens: Vec<LexerToken>) -> Self {
         Self {
             tokens,
@@ -35,118 +118,145 @@ impl Parser {
         }
     }
 
-    pub fn parse(&mut self) -> Vec<Argument> {
+    pub fn parse(&mut self) -> Result<Vec<Argument>> {
         let mut expressions: Vec<Argument> = Vec::new();
-        while self.position < self.tokens.len() {
-            let expr = self.parse_expression();
+        while !matches!(self.peek_token().token, Tokens::Eof) {
+            let expr = self.parse_expression()?;
             expressions.push(expr);
         }
-        expressions
+        Ok(expressions)
+    }
+
+    fn peek_token(&self) -> &LexerToken {
+        &self.tokens[self.position.min(self.tokens.len() - 1)]
     }
 
     fn next_token(&mut self) -> LexerToken {
-        if self.position >= self.tokens.len() {
-            return LexerToken {
-                var_type: Types::Unknown,
-                token: Tokens::Eof,
-                value: None,
-            };
+        let token = self.peek_token().clone();
+        if self.position < self.tokens.len() {
+            self.position += 1;
         }
-
-        let token = self.tokens[self.position].clone();
-        self.position += 1;
         token
     }
 
-    fn find_args(&mut self, token: LexerToken) -> Argument {
-        {
-            let mut arguments = Vec::new();
-            loop {
-                let peeked = self.next_token();
-
-                match peeked.token {
-                    Tokens::Punctuation(Punctuations::RParen) => {
-                        return Argument::Expression(Expression {
-                            function: token.value.unwrap(),
-                            arguments: arguments,
-                        });
-                    }
-                    Tokens::Punctuation(Punctuations::LParen) => {
-                        arguments.push(self.parse_expression());
-                    }
-                    Tokens::Eof => {
-                        return Argument::Expression(Expression {
-                            function: token.value.unwrap(),
-                            arguments: arguments,
-                        });
-                    }
-                    Tokens::Var(_) => arguments.push(Argument::LiteralVariable(LiteralVariable {
-                        var_type: peeked.var_type,
-                        value: peeked.value.unwrap(),
-                    })),
-                    Tokens::Keyword(_) => todo!(),
-                    Tokens::Operator(_) => todo!(),
-                }
-            }
+    fn error(message: &str, token: &LexerToken) -> Error {
+        match token.token {
+            Tokens::Eof => Error::Incomplete(message.to_string()).at(&token.span),
+            _ => Error::Parse(message.to_string()).at(&token.span),
         }
     }
 
-    fn parse_expression(&mut self) -> Argument {
-        let token = self.next_token();
-        match token.token {
-            Tokens::Punctuation(Punctuations::LParen) => {
-                let function = self.next_token();
-
-                match function.token {
-                    Tokens::Keyword(_) => {
-                        let mut arguments = Vec::new();
-                        loop {
-                            let peeked = self.next_token();
-                            match peeked.token {
-                                Tokens::Punctuation(Punctuations::RParen) => {
-                                    return Argument::Expression(Expression {
-                                        function: function.value.unwrap(),
-                                        arguments: arguments,
-                                    });
-                                }
-                                Tokens::Punctuation(Punctuations::LParen) => {
-                                    arguments.push(self.parse_expression());
-                                }
-                                Tokens::Eof => {
-                                    return Argument::Expression(Expression {
-                                        function: function.value.unwrap(),
-                                        arguments: arguments,
-                                    });
-                                }
-                                Tokens::Var(_) => {
-                                    arguments.push(Argument::LiteralVariable(LiteralVariable {
-                                        var_type: peeked.var_type,
-                                        value: peeked.value.unwrap(),
-                                    }))
-                                }
-                                Tokens::Keyword(_) => todo!(),
-                                Tokens::Operator(_) => todo!(),
-                            }
-                        }
+    fn parse_list(&mut self, open: &LexerToken) -> Result<Argument> {
+        let peeked = self.peek_token().clone();
+        match peeked.token {
+            Tokens::Punctuation(Punctuations::RParen) => {
+                self.next_token();
+                return Ok(Argument::LiteralVariable(LiteralVariable {
+                    value: Value::Nil,
+                    span: open.span.to(&peeked.span),
+                }));
+            }
+            Tokens::Punctuation(Punctuations::Dot) => return Err(Self::error("unexpected '.'", &peeked)),
+            _ => (),
+        }
+        let function = Box::new(self.parse_expression()?);
+
+        let mut arguments = Vec::new();
+        let mut rest = None;
+        let close = loop {
+            match self.peek_token().token {
+                Tokens::Punctuation(Punctuations::RParen) => break self.next_token(),
+                Tokens::Punctuation(Punctuations::Dot) => {
+                    self.next_token();
+                    rest = Some(Box::new(self.parse_expression()?));
+                    let close = self.next_token();
+                    match close.token {
+                        Tokens::Punctuation(Punctuations::RParen) => break close,
+                        _ => return Err(Self::error("expected ')' after dotted tail", &close)),
                     }
-                    _ => panic!("Expected LParen"),
                 }
+                Tokens::Eof => {
+                    return Err(Error::Incomplete("unexpected end of input".to_string()).at(&open.span))
+                }
+                _ => arguments.push(self.parse_expression()?),
             }
-            Tokens::Keyword(_) => self.find_args(token),
-            Tokens::Operator(_) => self.find_args(token),
-            Tokens::Punctuation(Punctuations::RParen) => {
-                return Argument::Expression(Expression {
-                    function: "".to_string(),
-                    arguments: Vec::new(),
-                });
-            }
-            Tokens::Eof => todo!(),
-            Tokens::Var(_) => {
-                return Argument::LiteralVariable(LiteralVariable {
-                    var_type: token.var_type,
-                    value: token.value.unwrap(),
-                })
+        };
+        Ok(Argument::Expression(Expression {
+            function,
+            arguments,
+            rest,
+            span: open.span.to(&close.span),
+        }))
+    }
+
+    fn literal(token: LexerToken) -> Result<Argument> {
+        let text = token.value.clone().unwrap_or_default();
+        let value = match token.var_type {
+            Types::Int | Types::Float | Types::Rational => match number::parse(&text) {
+                Some(value) => value,
+                None => return Err(Self::error(&format!("malformed number '{}'", text), &token)),
+            },
+            Types::String => Value::string(&text),
+            Types::Char => Value::Char(text.chars().next().unwrap()),
+            Types::Bool => Value::Bool(matches!(text.as_str(), "#t" | "#true")),
+            _ => Value::symbol(&text),
+        };
+        Ok(Argument::LiteralVariable(LiteralVariable {
+            value,
+            span: token.span,
+        }))
+    }
+
+    /// `#(a b c)` is self-evaluating: its elements are data, not expressions.
+    fn parse_vector(&mut self, open: &LexerToken) -> Result<Argument> {
+        let mut elements = Vec::new();
+        let close = loop {
+            let peeked = self.peek_token();
+            match peeked.token {
+                Tokens::Punctuation(Punctuations::RParen) => break self.next_token(),
+                Tokens::Punctuation(Punctuations::Dot) => return Err(Self::error("unexpected '.'", peeked)),
+                Tokens::Eof => {
+                    return Err(Error::Incomplete("unexpected end of input".to_string()).at(&open.span))
+                }
+                _ => elements.push(self.parse_expression()?.to_datum()),
             }
+        };
+        Ok(Argument::LiteralVariable(LiteralVariable {
+            value: Value::vector(elements),
+            span: open.span.to(&close.span),
+        }))
+    }
+
+    /// `'datum` reads as `(quote datum)`, and likewise `` `datum `` as
+    /// `(quasiquote datum)`, `,datum` as `(unquote datum)` and `,@datum` as
+    /// `(unquote-splicing datum)`.
+    fn parse_abbreviation(&mut self, token: LexerToken, name: &str) -> Result<Argument> {
+        let datum = self.parse_expression()?;
+        let span = token.span.to(datum.span());
+        Ok(Argument::Expression(Expression {
+            function: Box::new(Argument::LiteralVariable(LiteralVariable {
+                value: Value::symbol(name),
+                span: token.span,
+            })),
+            arguments: vec![datum],
+            rest: None,
+            span,
+        }))
+    }
+
+    fn parse_expression(&mut self) -> Result<Argument> {
+        let token = self.next_token();
+        match token.token {
+            Tokens::Punctuation(Punctuations::LParen) => self.parse_list(&token),
+            Tokens::Punctuation(Punctuations::RParen) => Err(Self::error("unexpected ')'", &token)),
+            Tokens::Punctuation(Punctuations::Dot) => Err(Self::error("unexpected '.'", &token)),
+            Tokens::Punctuation(Punctuations::Quote) => self.parse_abbreviation(token, "quote"),
+            Tokens::Punctuation(Punctuations::Quasiquote) => self.parse_abbreviation(token, "quasiquote"),
+            Tokens::Punctuation(Punctuations::Unquote) => self.parse_abbreviation(token, "unquote"),
+            Tokens::Punctuation(Punctuations::UnquoteSplicing) => self.parse_abbreviation(token, "unquote-splicing"),
+            Tokens::Punctuation(Punctuations::VectorOpen) => self.parse_vector(&token),
+            Tokens::Eof => Err(Self::error("unexpected end of input", &token)),
+            Tokens::Var(_) | Tokens::Keyword(_) | Tokens::Operator(_) => Self::literal(token),
         }
     }
 }
@@ -0,0 +1,174 @@
+; Library procedures written in Scheme, loaded into every runtime before the
+; program runs. They are the ones that need to call procedures passed to them.
+
+(define (vector-for-each proc vector . vectors)
+  (define all (cons vector vectors))
+  (define (shortest vectors length)
+    (if (null? vectors)
+        length
+        (shortest (cdr vectors)
+                  (if (< (vector-length (car vectors)) length)
+                      (vector-length (car vectors))
+                      length))))
+  (define (column vectors i)
+    (if (null? vectors)
+        '()
+        (cons (vector-ref (car vectors) i) (column (cdr vectors) i))))
+  (define length (shortest all (vector-length vector)))
+  (define i 0)
+  (while (< i length)
+    (apply proc (column all i))
+    (set! i (+ i 1))))
+
+(define (vector-map proc vector . vectors)
+  (define results '())
+  (define count 0)
+  (apply vector-for-each
+         (lambda elements
+           (set! results (cons (apply proc elements) results))
+           (set! count (+ count 1)))
+         vector
+         vectors)
+  (define result (make-vector count))
+  (while (pair? results)
+    (set! count (- count 1))
+    (vector-set! result count (car results))
+    (set! results (cdr results)))
+  result)
+
+; The index of the first char in `string` that is `char=?` to `pred`, or that
+; satisfies it when it is a procedure; #f if there is none.
+(define (string-index string pred)
+  (define matches? (if (char? pred) (lambda (c) (char=? c pred)) pred))
+  (let loop ((chars (string->list string)) (i 0))
+    (cond ((null? chars) #f)
+          ((matches? (car chars)) i)
+          (else (loop (cdr chars) (+ i 1))))))
+
+; dynamic-wind, and call/cc built on the machine's own continuations, which
+; only restore the stack: the continuation handed out first runs the after
+; thunks of the extents it leaves and the before thunks of the ones it enters.
+; Each winder is (size before . after), size being the length of the list of
+; winders it heads, so that leaving or entering extents costs only as much as
+; the extents involved.
+(define dynamic-wind #f)
+(let ((capture call-with-current-continuation)
+      (winders '()))
+  (define (size list)
+    (if (null? list) 0 (car (car list))))
+  (define (drop list n)
+    (if (= n 0) list (drop (cdr list) (- n 1))))
+  (define (common-tail x y)
+    (let ((lx (size x)) (ly (size y)))
+      (let loop ((x (if (> lx ly) (drop x (- lx ly)) x))
+                 (y (if (> ly lx) (drop y (- ly lx)) y)))
+        (if (eq? x y) x (loop (cdr x) (cdr y))))))
+  (define (rewind! to)
+    (define common (common-tail winders to))
+    (let leave ()
+      (unless (eq? winders common)
+        (let ((after (cdr (cdr (car winders)))))
+          (set! winders (cdr winders))
+          (after)
+          (leave))))
+    (let enter ((to to))
+      (unless (eq? to common)
+        (enter (cdr to))
+        ((car (cdr (car to))))
+        (set! winders to))))
+  (set! dynamic-wind
+        (let ()
+          (define (dynamic-wind before thunk after)
+            (before)
+            (set! winders (cons (cons (+ (size winders) 1) (cons before after)) winders))
+            (let ((result (thunk)))
+              (set! winders (cdr winders))
+              (after)
+              result))
+          dynamic-wind))
+  (set! call-with-current-continuation
+        (let ()
+          (define (call-with-current-continuation proc)
+            (define saved winders)
+            (capture
+             (lambda (k)
+               (define (continuation . results)
+                 (rewind! saved)
+                 (apply k results))
+               (proc continuation))))
+          call-with-current-continuation)))
+(define call/cc call-with-current-continuation)
+
+; Exceptions. The handlers installed by with-exception-handler form a stack
+; kept here. The built-in raise, used once no handler is left, stops the
+; program with the object as an uncaught exception. The machine passes
+; runtime errors to the raise defined here, as error objects.
+(define with-exception-handler #f)
+(define raise-continuable #f)
+(let ((uncaught raise)
+      (make-error error)
+      (handlers '()))
+  (define (with-handlers new thunk)
+    (define old handlers)
+    (dynamic-wind (lambda () (set! handlers new))
+                  thunk
+                  (lambda () (set! handlers old))))
+  (set! with-exception-handler
+        (let ()
+          (define (with-exception-handler handler thunk)
+            (with-handlers (cons handler handlers) thunk))
+          with-exception-handler))
+  (set! raise
+        (let ()
+          (define (raise obj)
+            (if (null? handlers)
+                (uncaught obj)
+                (let ((handler (car handlers)))
+                  (with-handlers (cdr handlers)
+                                 (lambda ()
+                                   (handler obj)
+                                   (raise (make-error "exception handler returned" obj)))))))
+          raise))
+  (set! raise-continuable
+        (let ()
+          (define (raise-continuable obj)
+            (if (null? handlers)
+                (uncaught obj)
+                (let ((handler (car handlers)))
+                  (with-handlers (cdr handlers) (lambda () (handler obj))))))
+          raise-continuable))
+  (set! error
+        (let ()
+          (define (error message . irritants)
+            (raise (apply make-error message irritants)))
+          error)))
+
+; (guard (var clause ...) body ...) runs body; if it raises, the clauses are
+; tried like cond's with var bound to the raised object, and the object is
+; raised again if none of them applies. The "clauses" rules are internal.
+(define-syntax guard
+  (syntax-rules (else)
+    ((_ "clauses" reraise (else result ...))
+     (begin result ...))
+    ((_ "clauses" reraise clause)
+     (cond clause (else reraise)))
+    ((_ "clauses" reraise clause more ...)
+     (cond clause (else (guard "clauses" reraise more ...))))
+    ((_ (var clause ...) body ...)
+     ((call/cc
+       (lambda (guard-k)
+         (with-exception-handler
+          (lambda (condition)
+            ((call/cc
+              (lambda (handler-k)
+                (guard-k
+                 (lambda ()
+                   (let ((var condition))
+                     (guard "clauses"
+                            (handler-k (lambda () (raise-continuable condition)))
+                            clause ...))))))))
+          (lambda ()
+            ; Returning normally rather than through guard-k leaves the
+            ; extent without unwinding to it.
+            (let ((result (begin body ...)))
+              (lambda () result))))))))))
@@ -0,0 +1,83 @@
+use std::io;
+use std::io::{BufRead, Write};
+
+use crate::error::Result;
+use crate::parser;
+use crate::runtime::Runtime;
+use crate::value::{Value, Written};
+
+const PROMPT: &str = "> ";
+const CONTINUATION: &str = ".. ";
+
+/// Reads expressions from stdin and evaluates them in one long-lived runtime.
+/// Input is buffered across lines until it parses as complete expressions.
+pub fn run() -> Result<()> {
+    session(io::stdin().lock(), &mut io::stdout(), &mut io::stderr())
+}
+
+/// The loop behind `run`: prompts and values go to `output`, diagnostics to `errors`.
+fn session(mut input: impl BufRead, output: &mut impl Write, errors: &mut impl Write) -> Result<()> {
+    let mut runtime = Runtime::new(Vec::new());
+    let mut buffer = String::new();
+
+    loop {
+        write!(output, "{}", if buffer.is_empty() { PROMPT } else { CONTINUATION })?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        buffer.push_str(&line);
+
+        let expressions = match parser::parse_source(&buffer, "<repl>") {
+            Ok(expressions) => expressions,
+            Err(error) if error.is_incomplete() => continue,
+            Err(error) => {
+                writeln!(errors, "{}", error.diagnostic())?;
+                buffer.clear();
+                continue;
+            }
+        };
+        buffer.clear();
+
+        for expression in &expressions {
+            match runtime.eval_toplevel(expression) {
+                Ok(Value::Unspecified) => (),
+                Ok(value) => writeln!(output, "{}", Written(&value))?,
+                Err(error) => {
+                    writeln!(errors, "{}", error.diagnostic())?;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a session over `input`, returning what it wrote to each stream.
+    fn session_over(input: &str) -> (String, String) {
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        session(input.as_bytes(), &mut output, &mut errors).unwrap();
+        (String::from_utf8(output).unwrap(), String::from_utf8(errors).unwrap())
+    }
+
+    #[test]
+    fn incomplete_input_continues_on_the_next_line() {
+        let (output, errors) = session_over("(define x\n  41)\n(+ x 1)\n");
+        assert_eq!(output, "> .. > 42\n> \n");
+        assert_eq!(errors, "");
+    }
+
+    #[test]
+    fn errors_are_reported_and_the_session_goes_on() {
+        let (output, errors) = session_over("(define x 1)\n(car x)\n)\nx\n");
+        assert_eq!(output, "> > > > 1\n> \n");
+        assert!(errors.starts_with("error: car: expected a pair, got integer at <repl>:1:1"), "{}", errors);
+        assert!(errors.contains("error: unexpected ')' at <repl>:1:1"), "{}", errors);
+    }
+}
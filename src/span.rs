@@ -0,0 +1,110 @@
+use std::fmt;
+use std::rc::Rc;
+
+/// A named piece of source text that spans point into.
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+/// A 1-based, inclusive range of source positions.
+#[derive(Clone)]
+pub struct Span {
+    pub source: Rc<Source>,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Source {
+    pub fn new(name: &str, text: &str) -> Rc<Self> {
+        Rc::new(Self {
+            name: name.to_string(),
+            text: text.to_string(),
+        })
+    }
+}
+
+impl Span {
+    /// The span covering everything from the start of `self` to the end of `end`.
+    pub fn to(&self, end: &Span) -> Span {
+        Span {
+            source: self.source.clone(),
+            line: self.line,
+            column: self.column,
+            end_line: end.end_line,
+            end_column: end.end_column,
+        }
+    }
+
+    /// Renders the first line of the span with a caret underline beneath it.
+    pub fn snippet(&self) -> String {
+        let text = self.source.text.lines().nth(self.line - 1).unwrap_or("");
+        let width = text.chars().count();
+        let end = if self.end_line == self.line {
+            self.end_column
+        } else {
+            width
+        };
+        let carets = end.saturating_sub(self.column) + 1;
+        let gutter = " ".repeat(self.line.to_string().len());
+        let indent = text
+            .chars()
+            .chain(std::iter::repeat(' '))
+            .take(self.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+
+        format!(
+            "{} |\n{} | {}\n{} | {}{}",
+            gutter,
+            self.line,
+            text,
+            gutter,
+            indent,
+            "^".repeat(carets),
+        )
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source.name, self.line, self.column)
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::eval;
+
+    #[test]
+    fn errors_point_at_the_offending_code() {
+        let error = eval("(define x 1)\n(display (+ x undefined-y))").unwrap_err();
+        assert_eq!(
+            error.diagnostic(),
+            "error: unbound variable 'undefined-y' at <test>:2:15\n  |\n2 | (display (+ x undefined-y))\n  |               ^^^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn a_span_over_several_lines_underlines_the_rest_of_the_first() {
+        let source = Source::new("file.scm", "(foo\n  bar)");
+        let span = Span {
+            source,
+            line: 1,
+            column: 1,
+            end_line: 2,
+            end_column: 6,
+        };
+        assert_eq!(span.to_string(), "file.scm:1:1");
+        assert_eq!(span.snippet(), "  |\n1 | (foo\n  | ^^^^");
+    }
+}
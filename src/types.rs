@@ -1,3 +1,5 @@
+use crate::span::Span;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Tokens {
@@ -31,7 +33,9 @@ pub enum Keywords {
 pub enum Types {
     Int,
     Float,
+    Rational,
     String,
+    Char,
     Bool,
     Unknown,
     Keyword,
@@ -42,6 +46,13 @@ pub enum Types {
 pub enum Punctuations {
     LParen,
     RParen,
+    Dot,
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
+    /// `#(`, which opens a vector literal.
+    VectorOpen,
 }
 
 #[allow(dead_code)]
@@ -65,4 +76,5 @@ pub struct LexerToken {
     pub var_type: Types,
     pub token: Tokens,
     pub value: Option<String>,
+    pub span: Span,
 }
@@ -0,0 +1,348 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::bytecode::Code;
+use crate::environment::Environment;
+use crate::error::Result;
+use crate::number;
+use crate::number::Rational;
+use crate::span::Span;
+use crate::vm::Continuation;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    BigInt(Rc<BigInt>),
+    Rational(Rc<Rational>),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    String(Rc<str>),
+    Symbol(Rc<str>),
+    /// An uninterned symbol from `gensym`. No other symbol has its name, and
+    /// no `Symbol` is ever the same as it.
+    Gensym(Rc<str>),
+    Unspecified,
+    Nil,
+    Pair(Rc<RefCell<Pair>>),
+    Vector(Rc<RefCell<Vec<Value>>>),
+    Procedure(Rc<Procedure>),
+    ErrorObject(Rc<ErrorObject>),
+}
+
+/// The characters with names in `#\name` syntax.
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("alarm", '\x07'),
+    ("backspace", '\x08'),
+    ("delete", '\x7f'),
+    ("escape", '\x1b'),
+    ("newline", '\n'),
+    ("null", '\0'),
+    ("return", '\r'),
+    ("space", ' '),
+    ("tab", '\t'),
+];
+
+/// What `error` creates and runtime errors are raised as.
+#[derive(Debug)]
+pub struct ErrorObject {
+    pub message: String,
+    pub irritants: Vec<Value>,
+    /// Where a runtime error happened.
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for ErrorObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for irritant in &self.irritants {
+            write!(f, " {}", Written(irritant))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pair {
+    pub car: Value,
+    pub cdr: Value,
+}
+
+/// Unlinks the cdr chain in a loop, so that dropping a long list does not
+/// recurse once per element. The walk stops at a pair something else still
+/// holds on to.
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut next = std::mem::replace(&mut self.cdr, Value::Nil);
+        while let Value::Pair(pair) = next {
+            next = match Rc::try_unwrap(pair) {
+                Ok(pair) => std::mem::replace(&mut pair.into_inner().cdr, Value::Nil),
+                Err(_) => break,
+            };
+        }
+    }
+}
+
+pub type PrimitiveFn = fn(&[Value]) -> Result<Value>;
+
+/// A built-in procedure; `max_args` of `None` means it is variadic.
+pub struct Primitive {
+    pub name: &'static str,
+    pub min_args: usize,
+    pub max_args: Option<usize>,
+    pub func: PrimitiveFn,
+}
+
+/// A closure created by `lambda`: compiled code plus the frame it was created in.
+pub struct Lambda {
+    pub code: Rc<Code>,
+    pub env: Option<Rc<Environment>>,
+}
+
+pub enum Procedure {
+    Primitive(Primitive),
+    Lambda(Lambda),
+    /// `apply`, which the VM handles itself since it calls back into Scheme.
+    Apply,
+    /// `call-with-current-continuation`, which captures the VM's state.
+    CallCC,
+    /// A captured continuation; calling it resumes that state.
+    Continuation(Continuation),
+}
+
+impl fmt::Debug for Procedure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Procedure::Primitive(primitive) => write!(f, "#<procedure {}>", primitive.name),
+            Procedure::Apply => write!(f, "#<procedure apply>"),
+            Procedure::CallCC => write!(f, "#<procedure call-with-current-continuation>"),
+            Procedure::Continuation(_) => write!(f, "#<continuation>"),
+            Procedure::Lambda(lambda) => match &lambda.code.name {
+                Some(name) => write!(f, "#<procedure {}>", name),
+                None => write!(f, "#<procedure>"),
+            },
+        }
+    }
+}
+
+impl Value {
+    pub fn string(s: &str) -> Self {
+        Value::String(Rc::from(s))
+    }
+
+    pub fn symbol(s: &str) -> Self {
+        Value::Symbol(Rc::from(s))
+    }
+
+    pub fn cons(car: Value, cdr: Value) -> Self {
+        Value::Pair(Rc::new(RefCell::new(Pair { car, cdr })))
+    }
+
+    pub fn list(values: Vec<Value>) -> Self {
+        Self::list_with_tail(values, Value::Nil)
+    }
+
+    /// `values` consed onto `tail`, giving an improper list unless it is `()`.
+    pub fn list_with_tail(values: Vec<Value>, tail: Value) -> Self {
+        values.into_iter().rev().fold(tail, |cdr, car| Value::cons(car, cdr))
+    }
+
+    pub fn vector(values: Vec<Value>) -> Self {
+        Value::Vector(Rc::new(RefCell::new(values)))
+    }
+
+    /// The elements of a proper list, or `None` for anything else.
+    pub fn to_vec(&self) -> Option<Vec<Value>> {
+        let mut values = Vec::new();
+        let mut current = self.clone();
+        loop {
+            current = match current {
+                Value::Nil => return Some(values),
+                Value::Pair(pair) => {
+                    let pair = pair.borrow();
+                    values.push(pair.car.clone());
+                    pair.cdr.clone()
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// `eqv?`: numbers and symbols compare by value, strings, pairs, vectors
+    /// and procedures by identity.
+    pub fn eqv(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::Rational(a), Value::Rational(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b),
+            (Value::Symbol(a), Value::Symbol(b)) | (Value::Gensym(a), Value::Gensym(b)) => a == b,
+            (Value::Unspecified, Value::Unspecified) | (Value::Nil, Value::Nil) => true,
+            (Value::Pair(a), Value::Pair(b)) => Rc::ptr_eq(a, b),
+            (Value::Vector(a), Value::Vector(b)) => Rc::ptr_eq(a, b),
+            (Value::Procedure(a), Value::Procedure(b)) => Rc::ptr_eq(a, b),
+            (Value::ErrorObject(a), Value::ErrorObject(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Every value except `#f` counts as true.
+    pub fn is_true(&self) -> bool {
+        !matches!(self, Value::Bool(false))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => "integer",
+            Value::Rational(_) => "rational",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "boolean",
+            Value::Char(_) => "char",
+            Value::String(_) => "string",
+            Value::Symbol(_) | Value::Gensym(_) => "symbol",
+            Value::Unspecified => "unspecified",
+            Value::Nil => "empty list",
+            Value::Pair(_) => "pair",
+            Value::Vector(_) => "vector",
+            Value::Procedure(_) => "procedure",
+            Value::ErrorObject(_) => "error object",
+        }
+    }
+}
+
+/// Formats a value the way `write` prints it: strings are quoted and escaped,
+/// so the output reads back as the same datum.
+pub struct Written<'a>(pub &'a Value);
+
+impl fmt::Display for Written<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.format(f, true)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.format(f, false)
+    }
+}
+
+impl Value {
+    fn format(&self, f: &mut fmt::Formatter, write: bool) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::BigInt(n) => write!(f, "{}", n),
+            Value::Rational(r) => write!(f, "{}/{}", r.numerator, r.denominator),
+            Value::Float(n) => write!(f, "{}", number::format_float(*n)),
+            Value::Bool(true) => write!(f, "#t"),
+            Value::Bool(false) => write!(f, "#f"),
+            Value::Char(c) if write => match CHAR_NAMES.iter().find(|(_, named)| named == c) {
+                Some((name, _)) => write!(f, "#\\{}", name),
+                None if c.is_control() => write!(f, "#\\x{:x}", *c as u32),
+                None => write!(f, "#\\{}", c),
+            },
+            Value::Char(c) => write!(f, "{}", c),
+            Value::String(s) if write => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Value::String(s) => write!(f, "{}", s),
+            Value::Symbol(s) | Value::Gensym(s) => write!(f, "{}", s),
+            Value::Unspecified => write!(f, "#<unspecified>"),
+            Value::Nil => write!(f, "()"),
+            Value::Pair(pair) => {
+                write!(f, "(")?;
+                pair.borrow().car.format(f, write)?;
+                let mut rest = pair.borrow().cdr.clone();
+                loop {
+                    rest = match rest {
+                        Value::Nil => break,
+                        Value::Pair(pair) => {
+                            write!(f, " ")?;
+                            let pair = pair.borrow();
+                            pair.car.format(f, write)?;
+                            pair.cdr.clone()
+                        }
+                        tail => {
+                            write!(f, " . ")?;
+                            tail.format(f, write)?;
+                            break;
+                        }
+                    }
+                }
+                write!(f, ")")
+            }
+            Value::Vector(values) => {
+                write!(f, "#(")?;
+                for (i, value) in values.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    value.format(f, write)?;
+                }
+                write!(f, ")")
+            }
+            Value::Procedure(procedure) => write!(f, "{:?}", procedure),
+            Value::ErrorObject(error) => write!(f, "#<error {}>", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::eval;
+
+    #[test]
+    fn values_keep_their_types() {
+        assert_eq!(eval("42").unwrap(), "42");
+        assert_eq!(eval("2.5").unwrap(), "2.5");
+        assert_eq!(eval("\"text\"").unwrap(), "\"text\"");
+        assert_eq!(eval("(define x 1)").unwrap(), "#<unspecified>");
+        assert_eq!(eval("(+ 1 2)").unwrap(), "3");
+    }
+
+    #[test]
+    fn arithmetic_on_a_string_is_a_type_error() {
+        let error = eval("(+ 1 \"a\")").unwrap_err();
+        assert!(error.to_string().starts_with("+: expected a number, got string"), "{}", error);
+    }
+
+    #[test]
+    fn long_lists_drop_without_recursing() {
+        drop(Value::list(vec![Value::Int(0); 1_000_000]));
+        let shared = Value::list(vec![Value::Int(1); 1_000_000]);
+        drop(Value::cons(Value::Int(0), shared.clone()));
+        assert_eq!(shared.to_vec().unwrap().len(), 1_000_000);
+        assert_eq!(eval("(define l (vector->list (make-vector 1000000 0))) (set! l 0) l").unwrap(), "0");
+    }
+
+    #[test]
+    fn lists_print_in_scheme_notation() {
+        let list = Value::list_with_tail(vec![Value::Int(1), Value::string("a")], Value::Bool(true));
+        assert_eq!(Written(&list).to_string(), "(1 \"a\" . #t)");
+        assert_eq!(list.to_string(), "(1 a . #t)");
+    }
+
+    #[test]
+    fn only_false_is_false() {
+        assert_eq!(eval("(list (if 0 'yes 'no) (if '() 'yes 'no) (if \"\" 'yes 'no) (if #f 'yes 'no))").unwrap(), "(yes yes yes no)");
+        assert_eq!(eval("(list #true #false (< 1 2) (= 1 2))").unwrap(), "(#t #f #t #f)");
+        assert_eq!(eval("(list (boolean? #f) (boolean? 0) (boolean? '()))").unwrap(), "(#t #f #f)");
+        assert_eq!(eval("(define n 0) (while (not (= n 3)) (set! n (+ n 1))) n").unwrap(), "3");
+    }
+}
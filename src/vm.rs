@@ -0,0 +1,633 @@
+use std::rc::Rc;
+
+use crate::builtins;
+use crate::bytecode::{Code, Op};
+use crate::environment::{Environment, Globals};
+use crate::error::{Error, Result};
+use crate::span::Span;
+use crate::runtime::PRELUDE_NAME;
+use crate::value::{ErrorObject, Lambda, Procedure, Value};
+
+/// An activation of a `Code` object: where it is executing, its local frame,
+/// and where its part of the value stack begins.
+#[derive(Debug, Clone)]
+struct CallFrame {
+    code: Rc<Code>,
+    pc: usize,
+    env: Option<Rc<Environment>>,
+    base: usize,
+}
+
+/// Frames that `call/cc` captured, with their part of the value stack and
+/// the frames below them. A segment never changes once made, so continuations
+/// and the running program share it; a frame is copied out of it again when
+/// execution returns into it.
+#[derive(Debug)]
+struct Segment {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    below: Option<Frozen>,
+}
+
+/// The bottom `frames` frames of a segment, which is never zero.
+#[derive(Debug, Clone)]
+struct Frozen {
+    segment: Rc<Segment>,
+    frames: usize,
+}
+
+impl Frozen {
+    /// The frozen frames with the top `count` left out.
+    fn without(&self, count: usize) -> Option<Frozen> {
+        match self.frames - count {
+            0 => self.segment.below.clone(),
+            frames => Some(Frozen {
+                segment: self.segment.clone(),
+                frames,
+            }),
+        }
+    }
+}
+
+/// Drops a long chain of segments without recursing down it.
+impl Drop for Segment {
+    fn drop(&mut self) {
+        let mut below = self.below.take();
+        while let Some(frozen) = below {
+            below = match Rc::try_unwrap(frozen.segment) {
+                Ok(mut segment) => segment.below.take(),
+                Err(_) => break,
+            };
+        }
+    }
+}
+
+/// The rest of a computation: the frames at the point `call/cc` was called.
+/// The frames share their environments with the running program, so
+/// resuming sees the variables as they are now.
+pub struct Continuation {
+    frozen: Frozen,
+}
+
+/// A stack machine that runs compiled code. Procedure calls push a
+/// `CallFrame` rather than recursing on the Rust stack.
+#[derive(Debug)]
+pub struct Vm {
+    pub globals: Globals,
+    /// The prelude's `raise`, which runtime errors are passed to as error
+    /// objects so that exception handlers can catch them. Unset while the
+    /// prelude itself loads.
+    pub raise: Option<Value>,
+    /// The runtime error last passed to `raise`, with the error object made
+    /// from it, so that it is reported as itself if nothing catches it.
+    raised: Option<(Rc<ErrorObject>, Error)>,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    /// The frames below `frames`, which the stack bottoms out in.
+    below: Option<Frozen>,
+    /// The most frames and stack slots that have been in use at once.
+    #[cfg(test)]
+    high_water: usize,
+}
+
+fn arity_error(procedure: &Procedure, found: usize) -> Error {
+    let (name, expected) = match procedure {
+        Procedure::Primitive(primitive) => (
+            primitive.name.to_string(),
+            match primitive.max_args {
+                Some(max) if max == primitive.min_args => max.to_string(),
+                Some(max) => format!("{} to {}", primitive.min_args, max),
+                None => format!("at least {}", primitive.min_args),
+            },
+        ),
+        Procedure::Apply => ("apply".to_string(), "at least 2".to_string()),
+        Procedure::CallCC => ("call-with-current-continuation".to_string(), "1".to_string()),
+        Procedure::Continuation(_) => ("continuation".to_string(), "0 to 1".to_string()),
+        Procedure::Lambda(lambda) => (
+            lambda.code.name.clone().unwrap_or("#<procedure>".to_string()),
+            match lambda.code.rest {
+                true => format!("at least {}", lambda.code.params),
+                false => lambda.code.params.to_string(),
+            },
+        ),
+    };
+    Error::Arity {
+        procedure: name,
+        expected,
+        found,
+    }
+}
+
+/// The error object a runtime error is raised as.
+fn error_object(error: &Error) -> Rc<ErrorObject> {
+    let (error, span) = match error {
+        Error::At { error, span } => (error.as_ref(), Some(span.clone())),
+        error => (error, None),
+    };
+    Rc::new(ErrorObject {
+        message: error.to_string(),
+        irritants: Vec::new(),
+        span,
+    })
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut globals = Globals::default();
+        builtins::register(&mut globals);
+        Self {
+            globals,
+            raise: None,
+            raised: None,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            below: None,
+            #[cfg(test)]
+            high_water: 0,
+        }
+    }
+
+    /// Runs top-level code to completion on a stack of its own. Afterwards,
+    /// even on error, the machine is as it was before, so it can keep being used.
+    pub fn execute(&mut self, code: Rc<Code>) -> Result<Value> {
+        let stack = std::mem::take(&mut self.stack);
+        let frames = std::mem::take(&mut self.frames);
+        let below = self.below.take();
+        self.frames.push(CallFrame {
+            code,
+            pc: 0,
+            env: None,
+            base: 0,
+        });
+
+        let result = self.run();
+        self.stack = stack;
+        self.frames = frames;
+        self.below = below;
+        result
+    }
+
+    /// Calls `procedure` with `args` from outside of running code, such as
+    /// from the macro expander.
+    pub fn apply(&mut self, procedure: Value, args: Vec<Value>, span: &Span) -> Result<Value> {
+        let mut code = Code::new(None);
+        for value in std::iter::once(procedure).chain(args) {
+            code.ops.push(Op::Constant(code.constants.len()));
+            code.spans.push(span.clone());
+            code.constants.push(value);
+        }
+        code.ops.push(Op::Call(code.constants.len() - 1));
+        code.ops.push(Op::Return);
+        code.spans.extend([span.clone(), span.clone()]);
+        self.execute(Rc::new(code))
+    }
+
+    /// Executes until the bottom frame returns.
+    fn run(&mut self) -> Result<Value> {
+        loop {
+            #[cfg(test)]
+            {
+                self.high_water = self.high_water.max(self.frames.len() + self.stack.len());
+            }
+            let frame = self.frames.last_mut().unwrap();
+            let op = frame.code.ops[frame.pc];
+            frame.pc += 1;
+
+            match self.step(op) {
+                Ok(false) => (),
+                Ok(true) => return Ok(self.pop()),
+                Err(error) => {
+                    let error = error.at(&self.error_span());
+                    match self.raise.clone() {
+                        Some(raise) if error.is_catchable() => {
+                            let object = error_object(&error);
+                            self.stack.push(raise);
+                            self.stack.push(Value::ErrorObject(object.clone()));
+                            self.raised = Some((object, error));
+                            self.call(1, false)?;
+                        }
+                        _ => return Err(self.uncaught(error)),
+                    }
+                }
+            }
+        }
+    }
+
+    /// An uncaught runtime error is reported as the error it was raised from,
+    /// rather than as the error object handlers saw.
+    fn uncaught(&mut self, error: Error) -> Error {
+        let mut inner = &error;
+        while let Error::At { error, .. } = inner {
+            inner = error;
+        }
+        match (inner, self.raised.take()) {
+            (Error::Raised(Value::ErrorObject(raised)), Some((object, original))) if Rc::ptr_eq(raised, &object) => {
+                original
+            }
+            _ => error,
+        }
+    }
+
+    /// Where an error raised now is reported: the innermost call outside the
+    /// prelude, so errors inside library procedures point at their caller.
+    fn error_span(&self) -> Span {
+        let span = |frame: &CallFrame| frame.code.spans[frame.pc.saturating_sub(1)].clone();
+        let outside = |frames: &[CallFrame]| {
+            frames.iter().rev().map(span).find(|span| span.source.name != PRELUDE_NAME)
+        };
+        let mut found = outside(&self.frames);
+        let mut below = self.below.as_ref();
+        while let (None, Some(frozen)) = (&found, below) {
+            found = outside(&frozen.segment.frames[..frozen.frames]);
+            below = frozen.segment.below.as_ref();
+        }
+        found.unwrap_or_else(|| span(self.frames.last().unwrap()))
+    }
+
+    /// Moves all frames into a new segment and returns them as a continuation.
+    /// The top frame is copied out again to keep running.
+    fn capture(&mut self) -> Continuation {
+        let segment = Segment {
+            stack: std::mem::take(&mut self.stack),
+            frames: std::mem::take(&mut self.frames),
+            below: self.below.take(),
+        };
+        let frozen = Frozen {
+            frames: segment.frames.len(),
+            segment: Rc::new(segment),
+        };
+        self.below = Some(frozen.clone());
+        self.thaw();
+        Continuation { frozen }
+    }
+
+    /// Copies the top frozen frame out to run, under the empty stack of the
+    /// frame that returned to it. Returns `false` if there is none.
+    fn thaw(&mut self) -> bool {
+        let Some(frozen) = self.below.take() else {
+            return false;
+        };
+        let segment = &frozen.segment;
+        let mut frame = segment.frames[frozen.frames - 1].clone();
+        let end = match segment.frames.get(frozen.frames) {
+            Some(above) => above.base,
+            None => segment.stack.len(),
+        };
+        self.stack.extend_from_slice(&segment.stack[frame.base..end]);
+        frame.base = 0;
+        self.frames.push(frame);
+        self.below = frozen.without(1);
+        true
+    }
+
+    fn frame(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn env(&self) -> &Environment {
+        self.frames
+            .last()
+            .unwrap()
+            .env
+            .as_ref()
+            .expect("local access outside of any frame")
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("value stack underflow")
+    }
+
+    /// Executes one op; returns `true` once the bottom frame has returned,
+    /// leaving its result on the stack.
+    #[inline(always)]
+    fn step(&mut self, op: Op) -> Result<bool> {
+        match op {
+            Op::Constant(index) => {
+                let value = self.frame().code.constants[index].clone();
+                self.stack.push(value);
+            }
+            Op::Unspecified => self.stack.push(Value::Unspecified),
+            Op::Pop => {
+                self.pop();
+            }
+            Op::GetGlobal(index) => match self.globals.get(index) {
+                Some(value) => self.stack.push(value.clone()),
+                None => return Err(Error::UnboundVariable(self.globals.name(index).to_string())),
+            },
+            Op::SetGlobal(index) => {
+                let value = self.pop();
+                if !self.globals.set(index, value) {
+                    return Err(Error::UnboundVariable(self.globals.name(index).to_string()));
+                }
+            }
+            Op::DefineGlobal(index) => {
+                let value = self.pop();
+                self.globals.define_at(index, value);
+            }
+            Op::GetLocal(outwards, slot) => {
+                let value = self.env().get(outwards, slot);
+                self.stack.push(value);
+            }
+            Op::SetLocal(outwards, slot) => {
+                let value = self.pop();
+                self.env().set(outwards, slot, value);
+            }
+            Op::Dup => {
+                let value = self.stack.last().unwrap().clone();
+                self.stack.push(value);
+            }
+            Op::Swap => {
+                let top = self.stack.len() - 1;
+                self.stack.swap(top, top - 1);
+            }
+            Op::Jump(target) => self.frame().pc = target,
+            Op::JumpIfFalse(target) => {
+                if !self.pop().is_true() {
+                    self.frame().pc = target;
+                }
+            }
+            Op::JumpIfFalseOrPop(target) => match self.stack.last().unwrap().is_true() {
+                true => {
+                    self.pop();
+                }
+                false => self.frame().pc = target,
+            },
+            Op::JumpIfTrueOrPop(target) => match self.stack.last().unwrap().is_true() {
+                true => self.frame().pc = target,
+                false => {
+                    self.pop();
+                }
+            },
+            Op::JumpUnlessMember(index, target) => {
+                let frame = self.frames.last_mut().unwrap();
+                let key = self.stack.last().unwrap();
+                let found = match &frame.code.constants[index] {
+                    Value::Vector(data) => data.borrow().iter().any(|datum| datum.eqv(key)),
+                    _ => unreachable!("case data is a vector"),
+                };
+                if !found {
+                    frame.pc = target;
+                }
+            }
+            Op::Closure(index) => {
+                let frame = self.frame();
+                let lambda = Lambda {
+                    code: frame.code.functions[index].clone(),
+                    env: frame.env.clone(),
+                };
+                self.stack.push(Value::Procedure(Rc::new(Procedure::Lambda(lambda))));
+            }
+            Op::Call(argc) => self.call(argc, false)?,
+            Op::TailCall(argc) => self.call(argc, true)?,
+            Op::Return => {
+                let value = self.pop();
+                let frame = self.frames.pop().unwrap();
+                self.stack.truncate(frame.base);
+                let done = self.frames.is_empty() && !self.thaw();
+                self.stack.push(value);
+                return Ok(done);
+            }
+            Op::PushScope(size) => {
+                let frame = self.frame();
+                frame.env = Some(Environment::new(size, frame.env.take()));
+            }
+            Op::PopScope => {
+                let frame = self.frame();
+                frame.env = frame.env.as_ref().and_then(|env| env.parent().cloned());
+            }
+        }
+        Ok(false)
+    }
+
+    /// Calls the procedure sitting below `argc` arguments on the stack.
+    /// Primitives run immediately; lambdas get a new frame; `apply` spreads its
+    /// last argument onto the stack and calls again; a continuation replaces
+    /// the stack and frames with the ones it captured, which are shared rather
+    /// than copied, so capturing and resuming take constant time. A `tail` call to a lambda
+    /// replaces the current frame; primitives leave that to the following `Return`.
+    fn call(&mut self, argc: usize, tail: bool) -> Result<()> {
+        let callee = self.stack.len() - argc - 1;
+        let procedure = match &self.stack[callee] {
+            Value::Procedure(procedure) => procedure,
+            value => {
+                return Err(Error::type_error(
+                    "apply",
+                    "a procedure",
+                    value.type_name(),
+                ))
+            }
+        };
+
+        match procedure.as_ref() {
+            Procedure::Primitive(primitive) => {
+                if argc < primitive.min_args || primitive.max_args.is_some_and(|max| argc > max) {
+                    return Err(arity_error(procedure, argc));
+                }
+                let result = (primitive.func)(&self.stack[callee + 1..])?;
+                self.stack.truncate(callee);
+                self.stack.push(result);
+            }
+            Procedure::Lambda(lambda) => {
+                let code = &lambda.code;
+                let arity_ok = match code.rest {
+                    true => argc >= code.params,
+                    false => argc == code.params,
+                };
+                if !arity_ok {
+                    return Err(arity_error(procedure, argc));
+                }
+                let (code, env) = (code.clone(), lambda.env.clone());
+
+                let mut slots = Vec::with_capacity(code.frame_size);
+                slots.extend(self.stack.drain(callee + 1..callee + 1 + code.params));
+                if code.rest {
+                    slots.push(Value::list(self.stack.drain(callee + 1..).collect()));
+                }
+                slots.resize(code.frame_size, Value::Unspecified);
+                self.stack.truncate(callee);
+
+                let base = match tail {
+                    true => {
+                        let frame = self.frames.pop().unwrap();
+                        self.stack.truncate(frame.base);
+                        frame.base
+                    }
+                    false => callee,
+                };
+                self.frames.push(CallFrame {
+                    code,
+                    pc: 0,
+                    env: Some(Environment::with_slots(slots, env)),
+                    base,
+                });
+            }
+            Procedure::Apply => {
+                if argc < 2 {
+                    return Err(arity_error(procedure, argc));
+                }
+                let list = self.pop();
+                let spread = list
+                    .to_vec()
+                    .ok_or_else(|| Error::type_error("apply", "a list", list.type_name()))?;
+                self.stack.remove(callee);
+                let argc = argc - 2 + spread.len();
+                self.stack.extend(spread);
+                return self.call(argc, tail);
+            }
+            Procedure::CallCC => {
+                if argc != 1 {
+                    return Err(arity_error(procedure, argc));
+                }
+                // (call/cc f) becomes (f k)
+                let receiver = self.pop();
+                self.pop();
+                let continuation = Procedure::Continuation(self.capture());
+                self.stack.push(receiver);
+                self.stack.push(Value::Procedure(Rc::new(continuation)));
+                return self.call(1, tail);
+            }
+            Procedure::Continuation(continuation) => {
+                if argc > 1 {
+                    return Err(arity_error(procedure, argc));
+                }
+                let value = match argc {
+                    1 => self.stack[callee + 1].clone(),
+                    _ => Value::Unspecified,
+                };
+                let frozen = continuation.frozen.clone();
+                self.stack.clear();
+                self.frames.clear();
+                self.below = Some(frozen);
+                self.thaw();
+                self.stack.push(value);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::parser;
+    use crate::runtime::eval;
+    use crate::value::Written;
+
+    /// Compiles and runs one form on `vm`.
+    fn execute(vm: &mut Vm, source: &str) -> Result<String> {
+        let form = parser::parse_source(source, "<test>")?.remove(0);
+        let code = Compiler::compile(&mut vm.globals, &form)?;
+        Ok(Written(&vm.execute(code)?).to_string())
+    }
+
+    /// Runs core forms like `execute` does; returns the last one's value
+    /// and the most frames and stack slots that were in use at once.
+    fn execute_measured(vm: &mut Vm, source: &str) -> (String, usize) {
+        vm.high_water = 0;
+        let mut result = Value::Unspecified;
+        for form in parser::parse_source(source, "<test>").unwrap() {
+            let code = Compiler::compile(&mut vm.globals, &form).unwrap();
+            result = vm.execute(code).unwrap();
+        }
+        (Written(&result).to_string(), vm.high_water)
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_space() {
+        let mut vm = Vm::new();
+        let mut check = |source: &str, expected: &str| {
+            let (result, space) = execute_measured(&mut vm, source);
+            assert_eq!(result, expected);
+            assert!(space < 10, "{} used {} slots", source, space);
+        };
+        check("(define (count i n) (if (< i n) (count (+ i 1) n) i)) (count 0 1000000)", "1000000");
+        check(
+            "(define (even? n) (if (= n 0) #t (odd? (- n 1))))
+             (define (odd? n) (if (= n 0) #f (even? (- n 1))))
+             (odd? 1000001)",
+            "#t",
+        );
+        check("(let loop ((i 0) (sum 0)) (if (= i 1000000) sum (loop (+ i 1) (+ sum i))))", "499999500000");
+        check(
+            "(define (down n)
+               (cond ((= n 0) 'done)
+                     (else (when #t (and #t (or #f (apply down (list (- n 1)))))))))
+             (down 1000000)",
+            "done",
+        );
+    }
+
+    #[test]
+    fn calls_not_in_tail_position_keep_their_frames() {
+        let mut vm = Vm::new();
+        let (result, space) = execute_measured(&mut vm, "(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1))))) (sum 1000)");
+        assert_eq!(result, "500500");
+        assert!(space > 1000, "{}", space);
+    }
+
+    #[test]
+    fn continuations_resume_where_they_were_captured() {
+        let source = "(let ((k #f) (n 0) (out '()))
+                        (set! out (cons (+ 100 (call/cc (lambda (c) (set! k c) 0))) out))
+                        (set! n (+ n 1))
+                        (if (< n 3) (k n))
+                        out)";
+        assert_eq!(eval(source).unwrap(), "(102 101 100)");
+        let source = "(define (walk tree yield)
+                        (cond ((null? tree) 'skip)
+                              ((pair? tree) (walk (car tree) yield) (walk (cdr tree) yield))
+                              (else (yield tree))))
+                      (define (generator tree)
+                        (define return #f)
+                        (define resume #f)
+                        (lambda ()
+                          (call/cc
+                            (lambda (r)
+                              (set! return r)
+                              (if resume
+                                  (resume 'next)
+                                  (begin
+                                    (walk tree (lambda (leaf) (call/cc (lambda (k) (set! resume k) (return leaf)))))
+                                    (return 'done)))))))
+                      (define next (generator '((1 2) (3 (4)) 5)))
+                      (list (next) (next) (next) (next) (next) (next))";
+        assert_eq!(eval(source).unwrap(), "(1 2 3 4 5 done)");
+        let source = "(let ((trail '()) (k #f) (count 0))
+                        (dynamic-wind (lambda () (set! trail (cons 'in trail)))
+                                      (lambda () (call/cc (lambda (c) (set! k c))))
+                                      (lambda () (set! trail (cons 'out trail))))
+                        (set! count (+ count 1))
+                        (if (< count 3) (k 'again))
+                        trail)";
+        assert_eq!(eval(source).unwrap(), "(out in out in out in)");
+    }
+
+    #[test]
+    fn capturing_does_not_copy_the_stack() {
+        // Copying 10000 frames on each of 20000 captures would not finish.
+        let source = "(define (captures i) (if (> i 0) (begin (call/cc (lambda (k) k)) (captures (- i 1))) 0))
+                      (define (deep n) (if (= n 0) (captures 20000) (+ 1 (deep (- n 1)))))
+                      (deep 10000)";
+        assert_eq!(eval(source).unwrap(), "10000");
+    }
+
+    #[test]
+    fn loops_run_on_the_vm() {
+        let source = "(define i 0)
+                      (define sum 0)
+                      (while (< i 100000)
+                        (set! sum (+ sum i))
+                        (set! i (+ i 1)))
+                      sum";
+        assert_eq!(eval(source).unwrap(), "4999950000");
+    }
+
+    #[test]
+    fn an_error_unwinds_the_machine() {
+        let mut vm = Vm::new();
+        assert!(execute(&mut vm, "(+ 1 ((lambda (x) (- x \"a\")) 5))").is_err());
+        assert!(vm.stack.is_empty() && vm.frames.is_empty());
+        assert_eq!(execute(&mut vm, "(+ 1 2)").unwrap(), "3");
+    }
+}